@@ -0,0 +1,95 @@
+// cargo bench --bench eval
+//
+// times a few programs through Engine::eval_str, the way the interpreter runs
+// a file
+use lex_lang::{Engine, EngineOptions};
+use std::time::{Duration, Instant};
+
+const ROUNDS: u32 = 5;
+
+fn fib_program(n: u32) -> String {
+    format!(
+        "(.def (fib 0) 0)
+         (.def (fib 1) 1)
+         (.def (fib N) (+ (fib (- N 2)) (fib (- N 1))))
+         (fib {})",
+        n
+    )
+}
+
+fn facts_program(facts: usize) -> String {
+    let mut program = String::new();
+    for i in 0..facts {
+        program.push_str(&format!("(.dec (likes p{} item{}))\n", i, i));
+    }
+    program
+}
+
+fn queries_program(facts: usize, queries: usize) -> String {
+    let mut program = String::new();
+    for i in 0..queries {
+        let n = (i * 7919) % facts;
+        program.push_str(&format!("(likes p{} item{})\n", n, n));
+    }
+    program
+}
+
+fn engine(compile: bool) -> Engine {
+    let options = EngineOptions::default().max_depth(100_000).compile(compile);
+    Engine::with_options(options).unwrap()
+}
+
+// best of ROUNDS, run is given a fresh engine every round. definitions are
+// compiled for the vm when the name says so
+fn bench<F: FnMut(&mut Engine) -> Duration>(name: &str, mut run: F) {
+    let compile = name.ends_with("(vm)");
+    let best = (0..ROUNDS)
+        .map(|_| run(&mut engine(compile)))
+        .min()
+        .unwrap_or_default();
+    println!("{:<32} {:>12.3} ms", name, best.as_secs_f64() * 1000.0);
+}
+
+fn time(engine: &mut Engine, program: &str) -> Duration {
+    let start = Instant::now();
+    if let Err(err) = engine.eval_str(program) {
+        panic!("{}", err);
+    }
+    start.elapsed()
+}
+
+fn main() {
+    let fib = fib_program(18);
+    bench("fib 18", |engine| time(engine, &fib));
+    bench("fib 18 (vm)", |engine| time(engine, &fib));
+
+    for facts in [1_000, 100_000] {
+        let load = facts_program(facts);
+        let queries = queries_program(facts, 10_000);
+
+        bench(&format!("load {} facts", facts), |engine| {
+            time(engine, &load)
+        });
+        let image = std::env::temp_dir().join(format!("lex-bench-{}.lxi", facts));
+        let mut source = engine(false);
+        time(&mut source, &load);
+        source.save_image(&image).unwrap();
+        bench(&format!("load {} facts from image", facts), |engine| {
+            let start = Instant::now();
+            engine.load_image(&image).unwrap();
+            start.elapsed()
+        });
+
+        bench(&format!("10000 queries on {} facts", facts), |engine| {
+            time(engine, &load);
+            time(engine, &queries)
+        });
+        bench(
+            &format!("10000 queries on {} facts (vm)", facts),
+            |engine| {
+                time(engine, &load);
+                time(engine, &queries)
+            },
+        );
+    }
+}
@@ -0,0 +1,316 @@
+//! Macros writing the [`Token`]s of native definitions in Lex syntax, re-exported
+//! by `lex_lang`, which is where they are documented.
+//!
+//! [`Token`]: https://docs.rs/lex-lang/latest/lex_lang/enum.Token.html
+
+use proc_macro::{Delimiter, Spacing, TokenStream, TokenTree};
+
+// the forms are read the way the parser of lex_lang reads them and the code
+// generated builds their tokens with the interner given, so they are written
+// in lex syntax but still checked at compile time
+
+#[proc_macro]
+pub fn sig(input: TokenStream) -> TokenStream {
+    let trees: Vec<TokenTree> = input.into_iter().collect();
+    let comma = trees
+        .iter()
+        .position(|tree| matches!(tree, TokenTree::Punct(p) if p.as_char() == ','));
+
+    let (symbols, form) = match comma {
+        Some(comma) if comma > 0 => (&trees[..comma], &trees[comma + 1..]),
+        _ => return error("expected sig!(symbols, form)"),
+    };
+
+    let symbols: TokenStream = symbols.iter().cloned().collect();
+    match form_source(form).and_then(|source| build(&source)) {
+        Ok(code) => with_symbols(&symbols.to_string(), &code),
+        Err(msg) => error(&msg),
+    }
+}
+
+#[proc_macro_attribute]
+pub fn native(attr: TokenStream, item: TokenStream) -> TokenStream {
+    match native_definition(attr, item) {
+        Ok(code) => code,
+        Err(msg) => error(&msg),
+    }
+}
+
+// the source of a form: a list is taken as written, so names like is-male
+// that rust reads as three tokens come out whole
+fn form_source(form: &[TokenTree]) -> Result<String, String> {
+    match form {
+        [] => Err("expected a form after the interner".to_owned()),
+        [tree] => Ok(tree
+            .span()
+            .source_text()
+            .unwrap_or_else(|| tree.to_string())),
+        trees => Ok(trees.iter().map(|tree| tree.to_string()).collect()),
+    }
+}
+
+fn with_symbols(symbols: &str, code: &str) -> TokenStream {
+    format!(
+        "{{ let __symbols: &::lex_lang::Interner = &({}); {} }}",
+        symbols, code
+    )
+    .parse()
+    .unwrap()
+}
+
+fn error(msg: &str) -> TokenStream {
+    format!("compile_error!({:?})", msg).parse().unwrap()
+}
+
+// the code building the token of the single form in source
+fn build(source: &str) -> Result<String, String> {
+    let words = lex(source)?;
+    let mut pos = 0;
+    let code = form(&words, &mut pos)?;
+    match words.get(pos) {
+        None => Ok(code),
+        Some(word) => Err(format!("unexpected {} after the form", word)),
+    }
+}
+
+fn lex(source: &str) -> Result<Vec<String>, String> {
+    let mut words = vec![];
+    let mut chars = source.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '(' | ')' => words.push(c.to_string()),
+            '"' => {
+                let mut word = String::from('"');
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated string".to_owned()),
+                    }
+                }
+                word.push('"');
+                words.push(word);
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut word = String::from(c);
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' || c == '"' {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                words.push(word);
+            }
+        }
+    }
+
+    Ok(words)
+}
+
+fn form(words: &[String], pos: &mut usize) -> Result<String, String> {
+    let word = match words.get(*pos) {
+        Some(word) => word,
+        None => return Err("missing bracket".to_owned()),
+    };
+    *pos += 1;
+
+    if word == ")" {
+        return Err("unexpected )".to_owned());
+    }
+    if word != "(" {
+        return atom(word);
+    }
+
+    let mut items = vec![];
+    loop {
+        match words.get(*pos).map(|word| word.as_str()) {
+            Some(")") => {
+                *pos += 1;
+                break;
+            }
+            Some(_) => items.push(form(words, pos)?),
+            None => return Err("missing bracket".to_owned()),
+        }
+    }
+
+    Ok(format!(
+        "::lex_lang::Token::list(::std::vec![{}])",
+        items.join(", ")
+    ))
+}
+
+fn atom(word: &str) -> Result<String, String> {
+    let first = word.chars().next().unwrap_or_default();
+    let is_name = word[first.len_utf8()..]
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '/');
+
+    let code = match first {
+        '"' => format!("__symbols.str({:?})", &word[1..word.len() - 1]),
+        '0'..='9' => match word.parse::<f32>() {
+            Ok(num) => format!("::lex_lang::Token::Number({:?}f32)", num),
+            Err(_) => return Err(format!("invalid number {}", word)),
+        },
+        'A'..='Z' if is_name => format!("__symbols.var({:?})", word),
+        '.' | ':' if is_name => format!("__symbols.keyword({:?})", word),
+        '_' if is_name => format!("::lex_lang::Token::Wildcard(__symbols.intern({:?}))", word),
+        'a'..='z' if is_name => match word {
+            "true" | "false" => format!("::lex_lang::Token::Bool({})", word),
+            _ => format!("__symbols.str({:?})", word),
+        },
+        _ => match word {
+            "+" | "-" | "<" | ">" | "<=" | ">=" | "*" | "=" | "**" | "%" | "/" => {
+                format!("__symbols.binary_op({:?})", word)
+            }
+            "!" => format!("::lex_lang::Token::UnaryOp(__symbols.intern({:?}))", word),
+            _ => return Err(format!("invalid symbol {}", word)),
+        },
+    };
+
+    Ok(code)
+}
+
+// #[native] fn concat(a: String, b: String) -> String becomes
+// fn concat(symbols: &Interner) -> Definition, the definition of
+// (concat A B) calling the function as written
+fn native_definition(attr: TokenStream, item: TokenStream) -> Result<TokenStream, String> {
+    let trees: Vec<TokenTree> = item.into_iter().collect();
+    let at = trees
+        .iter()
+        .position(|tree| matches!(tree, TokenTree::Ident(i) if i.to_string() == "fn"))
+        .ok_or("#[native] only applies to functions")?;
+
+    // attributes and visibility stay on the function giving the definition
+    let (outer, inner) = trees.split_at(at);
+    let (visibility, attributes): (Vec<&TokenTree>, Vec<&TokenTree>) = {
+        let vis = outer
+            .iter()
+            .position(|tree| matches!(tree, TokenTree::Ident(i) if i.to_string() == "pub"));
+        match vis {
+            Some(vis) => (outer[vis..].iter().collect(), outer[..vis].iter().collect()),
+            None => (vec![], outer.iter().collect()),
+        }
+    };
+
+    let name = match inner.get(1) {
+        Some(TokenTree::Ident(name)) => name.to_string(),
+        _ => return Err("expected the name of the function".to_owned()),
+    };
+    let params = match inner.get(2) {
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Parenthesis => {
+            parameters(group.stream())?
+        }
+        Some(TokenTree::Punct(p)) if p.as_char() == '<' => {
+            return Err("#[native] functions can't be generic".to_owned())
+        }
+        _ => return Err("expected the parameters of the function".to_owned()),
+    };
+
+    let head = match attr.into_iter().collect::<Vec<_>>().as_slice() {
+        [] => name.replace('_', "-"),
+        [TokenTree::Literal(lit)] => {
+            let lit = lit.to_string();
+            match lit.strip_prefix('"').and_then(|lit| lit.strip_suffix('"')) {
+                Some(head) => head.to_owned(),
+                None => return Err("expected the name to define as a string".to_owned()),
+            }
+        }
+        _ => return Err("expected #[native] or #[native(\"name\")]".to_owned()),
+    };
+
+    let mut signature = vec![head];
+    signature.extend(params.iter().map(|param| variable(param)));
+    let signature = build(&format!("({})", signature.join(" ")))?;
+
+    let attributes: TokenStream = attributes.into_iter().cloned().collect();
+    let visibility: TokenStream = visibility.into_iter().cloned().collect();
+    let inner: TokenStream = inner.iter().cloned().collect();
+    let code = format!(
+        "{} {} fn {}(symbols: &::lex_lang::Interner) -> ::lex_lang::Definition {{
+            {}
+            let __symbols = symbols;
+            ::lex_lang::Definition::function({}, {})
+        }}",
+        attributes, visibility, name, inner, signature, name
+    );
+
+    code.parse()
+        .map_err(|_| "couldn't expand #[native]".to_owned())
+}
+
+// the names of the parameters, splitting on the commas that aren't inside
+// the brackets of a type
+fn parameters(stream: TokenStream) -> Result<Vec<String>, String> {
+    let mut names = vec![];
+    let mut param: Vec<TokenTree> = vec![];
+    let mut depth = 0;
+    let mut after_dash = false;
+
+    for tree in stream.into_iter().chain(std::iter::once(comma())) {
+        if let TokenTree::Punct(p) = &tree {
+            match p.as_char() {
+                '<' => depth += 1,
+                // the arrow of a fn type isn't a closing bracket
+                '>' if !after_dash => depth -= 1,
+                ',' if depth == 0 => {
+                    if !param.is_empty() {
+                        names.push(parameter(&param)?);
+                    }
+                    param.clear();
+                    continue;
+                }
+                _ => {}
+            }
+            after_dash = p.as_char() == '-' && p.spacing() == Spacing::Joint;
+        } else {
+            after_dash = false;
+        }
+        param.push(tree);
+    }
+
+    Ok(names)
+}
+
+fn comma() -> TokenTree {
+    TokenTree::Punct(proc_macro::Punct::new(',', Spacing::Alone))
+}
+
+fn parameter(param: &[TokenTree]) -> Result<String, String> {
+    let param = match param.first() {
+        Some(TokenTree::Ident(i)) if i.to_string() == "mut" => &param[1..],
+        _ => param,
+    };
+
+    match param {
+        [TokenTree::Ident(name), TokenTree::Punct(colon), ..] if colon.as_char() == ':' => {
+            match name.to_string().as_str() {
+                "self" => Err("#[native] functions can't take self".to_owned()),
+                name => Ok(name.to_owned()),
+            }
+        }
+        _ => Err("#[native] parameters must be plain names".to_owned()),
+    }
+}
+
+// first_name is the variable FirstName
+fn variable(param: &str) -> String {
+    let name: String = param
+        .split('_')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect();
+
+    match name.is_empty() {
+        true => "Arg".to_owned(),
+        false => name,
+    }
+}
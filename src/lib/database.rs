@@ -0,0 +1,341 @@
+use crate::error::{DatabaseError, LexError};
+use crate::image::{checksum, decode_tokens, encode_tokens, MAX_NESTING};
+use crate::symbol::Interner;
+use crate::token::Token;
+
+use crate::engine::io_error;
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+// the dynamic database is kept as a log of the changes made to it, appended to
+// as they are made and replayed in order to get it back:
+//
+//   magic    b"LXD\0"
+//   version  u32
+//   records  each a u32 length and a u32 FNV-1a checksum of its payload,
+//            then the payload: a kind byte followed by the record's tokens
+//
+// numbers are little endian. a last record cut short or failing its checksum,
+// as a crash while writing it would leave, is dropped. compaction rewrites
+// the log as a record for each private name, macro, and pattern given to
+// .table or .lazy, and a single assert for each clause left
+const MAGIC: &[u8; 4] = b"LXD\0";
+pub const VERSION: u32 = 3;
+const HEADER_LEN: usize = 8;
+
+const ASSERT: u8 = 0;
+const RETRACT: u8 = 1;
+const PRIVATE: u8 = 2;
+const MACRO: u8 = 3;
+const TABLE: u8 = 4;
+const LAZY: u8 = 5;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    // a clause added by .def or .dec, as its head and body
+    Assert(Token, Token),
+    // the pattern given to .retract
+    Retract(Token),
+    // the qualified name of a private definition of an imported file
+    Private(Token),
+    // a macro given by .defmacro, as its signature and template
+    Macro(Token, Token),
+    // the pattern given to .table
+    Table(Token),
+    // the pattern given to .lazy
+    Lazy(Token),
+}
+
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    // records in the log, which tells how much compacting it would save
+    records: usize,
+}
+
+impl Journal {
+    // opens the log at path, creating it if there is none, and gives back the
+    // changes it holds in the order they were made
+    pub fn open(path: &Path, symbols: &Interner) -> Result<(Journal, Vec<Change>), LexError> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => vec![],
+            Err(err) => return Err(io_error(path, err)),
+        };
+
+        let (changes, end) = if bytes.is_empty() {
+            (vec![], 0)
+        } else {
+            replay(&bytes, symbols)
+                .map_err(|err| LexError::Database(path.display().to_string(), err))?
+        };
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| io_error(path, err))?;
+        if end == 0 {
+            file.write_all(&header())
+                .map_err(|err| io_error(path, err))?;
+        } else if end < bytes.len() {
+            file.set_len(end as u64)
+                .map_err(|err| io_error(path, err))?;
+        }
+
+        let journal = Journal {
+            path: path.to_owned(),
+            file,
+            records: changes.len(),
+        };
+        Ok((journal, changes))
+    }
+
+    pub fn records(&self) -> usize {
+        self.records
+    }
+
+    // writes changes at the end of the log, or none of them if one nests
+    // too deeply to be read back
+    pub fn append(&mut self, changes: &[Change], symbols: &Interner) -> Result<(), LexError> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        let mut bytes = vec![];
+        for change in changes {
+            write_record(&mut bytes, change, symbols)
+                .map_err(|err| LexError::Database(self.path.display().to_string(), err))?;
+        }
+
+        self.file
+            .write_all(&bytes)
+            .and_then(|_| self.file.sync_data())
+            .map_err(|err| io_error(&self.path, err))?;
+        self.records += changes.len();
+        Ok(())
+    }
+
+    // rewrites the log as changes, which make the clauses left without any
+    // of the changes undone since. the new log is written aside and then
+    // moved over the old one, so a crash leaves either
+    pub fn compact(&mut self, changes: &[Change], symbols: &Interner) -> Result<(), LexError> {
+        let mut bytes = header();
+        for change in changes {
+            write_record(&mut bytes, change, symbols)
+                .map_err(|err| LexError::Database(self.path.display().to_string(), err))?;
+        }
+
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let res = File::create(&tmp)
+            .and_then(|mut file| file.write_all(&bytes).and_then(|_| file.sync_all()))
+            .and_then(|_| fs::rename(&tmp, &self.path))
+            .and_then(|_| OpenOptions::new().append(true).open(&self.path));
+
+        self.file = res.map_err(|err| io_error(&self.path, err))?;
+        self.records = changes.len();
+        Ok(())
+    }
+}
+
+fn header() -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes
+}
+
+fn write_record(
+    bytes: &mut Vec<u8>,
+    change: &Change,
+    symbols: &Interner,
+) -> Result<(), DatabaseError> {
+    let (kind, tokens) = match change {
+        Change::Assert(head, body) => (ASSERT, vec![head.clone(), body.clone()]),
+        Change::Retract(pattern) => (RETRACT, vec![pattern.clone()]),
+        Change::Private(name) => (PRIVATE, vec![name.clone()]),
+        Change::Macro(signature, template) => (MACRO, vec![signature.clone(), template.clone()]),
+        Change::Table(pattern) => (TABLE, vec![pattern.clone()]),
+        Change::Lazy(pattern) => (LAZY, vec![pattern.clone()]),
+    };
+    let mut payload = vec![kind];
+    // nesting too deeply is all that keeps tokens from being written
+    let tokens = encode_tokens(&tokens, symbols)
+        .map_err(|_| DatabaseError::TooDeep { limit: MAX_NESTING })?;
+    payload.extend(tokens);
+
+    bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&checksum(&payload).to_le_bytes());
+    bytes.append(&mut payload);
+    Ok(())
+}
+
+// the changes of a log, and where its last whole record ends
+fn replay(bytes: &[u8], symbols: &Interner) -> Result<(Vec<Change>, usize), DatabaseError> {
+    if bytes.len() < HEADER_LEN || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(DatabaseError::NotADatabase);
+    }
+
+    let version = u32_at(bytes, MAGIC.len());
+    if version != VERSION {
+        return Err(DatabaseError::UnsupportedVersion {
+            found: version,
+            expected: VERSION,
+        });
+    }
+
+    let mut changes = vec![];
+    let mut pos = HEADER_LEN;
+    while bytes.len() - pos >= 8 {
+        let len = u32_at(bytes, pos) as usize;
+        let sum = u32_at(bytes, pos + 4);
+        let end = pos + 8 + len;
+        if end > bytes.len() {
+            break;
+        }
+
+        let payload = &bytes[pos + 8..end];
+        if checksum(payload) != sum {
+            // only the last record can have been left half written
+            if end == bytes.len() {
+                break;
+            }
+            return Err(DatabaseError::ChecksumMismatch);
+        }
+
+        let change =
+            read_change(payload, symbols).ok_or(DatabaseError::InvalidRecord(changes.len() + 1))?;
+        changes.push(change);
+        pos = end;
+    }
+
+    Ok((changes, pos))
+}
+
+// the change a record's payload holds, if it is a valid one
+fn read_change(payload: &[u8], symbols: &Interner) -> Option<Change> {
+    let (kind, tokens) = payload.split_first()?;
+    let tokens = decode_tokens(tokens, symbols).ok()?;
+
+    match (*kind, &tokens[..]) {
+        (ASSERT, [head, body]) => Some(Change::Assert(head.clone(), body.clone())),
+        (RETRACT, [pattern]) => Some(Change::Retract(pattern.clone())),
+        (PRIVATE, [name]) => Some(Change::Private(name.clone())),
+        (MACRO, [signature, template]) => Some(Change::Macro(signature.clone(), template.clone())),
+        (TABLE, [pattern]) => Some(Change::Table(pattern.clone())),
+        (LAZY, [pattern]) => Some(Change::Lazy(pattern.clone())),
+        _ => None,
+    }
+}
+
+fn u32_at(bytes: &[u8], pos: usize) -> u32 {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(&bytes[pos..pos + 4]);
+    u32::from_le_bytes(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{Engine, EngineOptions};
+    use crate::image::MAX_NESTING;
+    use crate::testing::{eval, TempDir};
+
+    fn open(path: &Path) -> Engine {
+        Engine::with_options(EngineOptions::default().database(path)).unwrap()
+    }
+
+    // the records of the log at path
+    fn records(path: &Path) -> usize {
+        Journal::open(path, &Interner::new()).unwrap().0.records()
+    }
+
+    #[test]
+    fn database_survives_restarts_and_compaction() {
+        let dir = TempDir::new("database");
+        let path = dir.join("facts.lxd");
+
+        let mut engine = open(&path);
+        eval(&mut engine, "(.dec (likes a tea)) (.dec (likes b tea))");
+        eval(
+            &mut engine,
+            "(.def (happy X) (likes X tea)) (.retract (likes a Y))",
+        );
+        drop(engine);
+
+        let mut engine = open(&path);
+        let query = "(happy a) (happy b)";
+        assert_eq!(eval(&mut engine, query), "((likes a tea) true)");
+        assert_eq!(records(&path), 4);
+
+        engine.compact_database().unwrap();
+        drop(engine);
+
+        let mut engine = open(&path);
+        assert_eq!(eval(&mut engine, query), "((likes a tea) true)");
+        assert_eq!(records(&path), 2);
+    }
+
+    #[test]
+    fn tabled_and_lazy_patterns_are_kept() {
+        let dir = TempDir::new("patterns");
+        let path = dir.join("facts.lxd");
+        let query = "(first 1 (.throw no)) (n 2) (.table-answers (n X))";
+
+        let mut engine = open(&path);
+        eval(
+            &mut engine,
+            "(.def (first X Y) X) (.lazy (first X Y)) (.def (n X) X) (.table (n X))",
+        );
+        drop(engine);
+
+        let mut engine = open(&path);
+        assert_eq!(eval(&mut engine, query), "(1 2 (((n 2) 2)))");
+        engine.compact_database().unwrap();
+        drop(engine);
+
+        // a record for each clause and pattern, leaving out the patterns
+        // of the modules loaded
+        let mut engine = open(&path);
+        assert_eq!(eval(&mut engine, query), "(1 2 (((n 2) 2)))");
+        assert_eq!(records(&path), 4);
+    }
+
+    #[test]
+    fn changes_too_deep_to_read_back_are_not_logged() {
+        let dir = TempDir::new("deep");
+        let path = dir.join("facts.lxd");
+        let deep = "(".repeat(MAX_NESTING) + "x" + &")".repeat(MAX_NESTING);
+
+        let mut engine = open(&path);
+        eval(&mut engine, "(.dec (likes a tea))");
+        assert!(matches!(
+            engine.eval_str(&format!("(.dec (deep {}))", deep)),
+            Err(LexError::Database(_, DatabaseError::TooDeep { .. }))
+        ));
+        drop(engine);
+
+        let mut engine = open(&path);
+        assert_eq!(eval(&mut engine, "(likes a tea)"), "(true)");
+        assert_eq!(records(&path), 1);
+    }
+
+    #[test]
+    fn logs_are_reported_as_logs() {
+        let dir = TempDir::new("reported");
+        let path = dir.write("facts.lxd", "(.dec a)");
+
+        let err = Engine::with_options(EngineOptions::default().database(&path))
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "database \"{}\": not a database log written by lex",
+                path.display()
+            )
+        );
+    }
+}
@@ -1,39 +1,720 @@
-use crate::lib::error::LexError;
-use crate::lib::evaluator::{
-    definition::{def::Def, math::Math, misc::Misc},
-    Evaluator,
+use crate::database::{Change, Journal};
+use crate::error::{LexError, ParseError};
+use crate::evaluator::{
+    budget::CancellationToken,
+    definition::{
+        def::{match_variable, Def},
+        exception::Exception,
+        math::Math,
+        misc::Misc,
+        native::{self, NativeFunction},
+        store::Store,
+        stream::Streams,
+        table::Tabling,
+        Module,
+    },
+    match_token, Evaluator, Snapshot, DEFAULT_MAX_DEPTH,
 };
-use crate::lib::parser::Parser;
-use crate::lib::token::Token;
+use crate::image::{self, Image};
+use crate::manifest;
+use crate::parser::Parser;
+use crate::printer::render_token;
+use crate::symbol::Interner;
+use crate::token::Token;
 
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Settings of an [`Engine`], read when it is created. Fields may be added
+/// later, so options are built from the defaults:
+///
+/// ```
+/// use lex_lang::{Engine, EngineOptions};
+///
+/// let options = EngineOptions::default().lazy(true).max_depth(1000);
+/// let mut engine = Engine::with_options(options)?;
+/// # Ok::<(), lex_lang::LexError>(())
+/// ```
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct EngineOptions {
+    /// How deeply evaluations that aren't tail calls may nest before failing
+    /// with [`EvalError::StackOverflow`](crate::EvalError::StackOverflow).
+    pub max_depth: usize,
+    /// How many definitions a single call to [`Engine::eval_str`] may run.
+    pub max_steps: Option<u64>,
+    /// How long a single call to [`Engine::eval_str`] may run.
+    pub timeout: Option<Duration>,
+    /// Lets another thread stop the evaluation in progress.
+    pub cancellation: CancellationToken,
+    /// Runs the clauses given by `.def` and `.dec` on a small vm instead of
+    /// walking their tokens, with the same results.
+    pub compile: bool,
+    /// A log the clauses given by `.def`, `.dec` and `.retract` are written
+    /// to, and read back from when the engine is created.
+    pub database: Option<PathBuf>,
+    /// Whether every call passes its arguments unevaluated, each evaluated
+    /// once a clause needs its value. `(.lazy pattern)` makes only the calls
+    /// matching the pattern lazy.
+    pub lazy: bool,
+    /// The directories `.import` looks in after the one of the importing
+    /// file, in order.
+    pub search_path: Vec<PathBuf>,
+}
+
+impl Default for EngineOptions {
+    fn default() -> Self {
+        EngineOptions {
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_steps: None,
+            timeout: None,
+            cancellation: CancellationToken::new(),
+            compile: false,
+            database: None,
+            lazy: false,
+            search_path: vec![],
+        }
+    }
+}
+
+impl EngineOptions {
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub fn max_steps(mut self, max_steps: u64) -> Self {
+        self.max_steps = Some(max_steps);
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn cancellation(mut self, cancellation: CancellationToken) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    pub fn compile(mut self, compile: bool) -> Self {
+        self.compile = compile;
+        self
+    }
+
+    pub fn database<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.database = Some(path.into());
+        self
+    }
+
+    pub fn lazy(mut self, lazy: bool) -> Self {
+        self.lazy = lazy;
+        self
+    }
+
+    /// Adds a directory for `.import` to look in after those already given.
+    pub fn search_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.search_path.push(dir.into());
+        self
+    }
+}
+
+/// The values a query gave to the variables of its pattern.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Bindings {
+    values: BTreeMap<String, Token>,
+}
+
+impl Bindings {
+    /// The value of the variable called `name`.
+    pub fn get(&self, name: &str) -> Option<&Token> {
+        self.values.get(name)
+    }
+
+    /// The variables and their values, ordered by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Token)> {
+        self.values
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+/// Parses and evaluates Lex source, keeping what it defines for the
+/// evaluations that follow.
 pub struct Engine {
+    // shared with the parser and the evaluator, so a name is given the same
+    // symbol wherever it comes from
+    symbols: Interner,
     parser: Parser,
     evalutator: Evaluator,
+    journal: Option<Journal>,
+}
+
+// a log is compacted on start once it holds this many more records than
+// twice the clauses it gives
+const COMPACTION_SLACK: usize = 1024;
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new()
+    }
 }
 
 impl Engine {
+    /// An engine with the default options and the built-in definitions.
     pub fn new() -> Self {
+        Engine::build(EngineOptions::default())
+    }
+
+    /// An engine with the given options, which fails only if the database
+    /// they name can't be read.
+    pub fn with_options(options: EngineOptions) -> Result<Self, LexError> {
+        let mut e = Engine::build(options.clone());
+        if let Some(path) = &options.database {
+            e.open_database(path)?;
+        }
+        Ok(e)
+    }
+
+    fn build(options: EngineOptions) -> Self {
+        let symbols = Interner::new();
         let mut e = Engine {
-            parser: Parser {},
-            evalutator: Evaluator::new(),
+            parser: Parser::new(symbols.clone()),
+            evalutator: Evaluator::new(symbols.clone()),
+            symbols,
+            journal: None,
         };
 
-        e.evalutator.load(Misc {});
-        e.evalutator.load(Def {});
-        e.evalutator.load(Math {});
+        e.evalutator.set_max_depth(options.max_depth);
+        e.evalutator.set_max_steps(options.max_steps);
+        e.evalutator.set_timeout(options.timeout);
+        e.evalutator.set_cancellation_token(options.cancellation);
+        e.evalutator.set_compile(options.compile);
+        e.evalutator.set_lazy(options.lazy);
+        e.evalutator.set_search_path(options.search_path);
+
+        let builtins: [Arc<dyn Module>; 7] = [
+            Arc::new(Misc),
+            Arc::new(Def),
+            Arc::new(Math),
+            Arc::new(Exception),
+            Arc::new(Tabling),
+            Arc::new(Store),
+            Arc::new(Streams),
+        ];
+        for module in builtins {
+            e.evalutator
+                .load_module(module)
+                .expect("built-in modules have distinct names and no dependencies");
+        }
         e
     }
 
-    pub fn parse(&mut self, input: String) -> Result<Token, LexError> {
-        let mut str = input.clone();
+    fn open_database(&mut self, path: &Path) -> Result<(), LexError> {
+        let (journal, changes) = Journal::open(path, &self.symbols)?;
+        for change in changes {
+            match change {
+                Change::Assert(head, body) => self.evalutator.add_clause(&head, &body),
+                Change::Retract(pattern) => self.evalutator.retract(&pattern),
+                Change::Private(name) => self.evalutator.add_private_name(name),
+                Change::Macro(signature, template) => {
+                    self.evalutator.add_macro(signature, template)
+                }
+                Change::Table(pattern) => self.evalutator.add_tabled_pattern(pattern),
+                Change::Lazy(pattern) => self.evalutator.add_lazy_pattern(pattern),
+            }
+        }
+
+        let records = journal.records();
+        self.journal = Some(journal);
+        if records > 2 * self.evalutator.clauses().len() + COMPACTION_SLACK {
+            self.compact_database()?;
+        }
+        Ok(())
+    }
+
+    /// Adds the definitions of a module after those already there. The
+    /// modules it depends on must be loaded first, and no module of the same
+    /// name may be.
+    pub fn load_module<T: Module>(&mut self, module: T) -> Result<(), LexError> {
+        self.evalutator.load_module(Arc::new(module))
+    }
+
+    /// Removes the definitions of the module called `name`, which no other
+    /// module loaded may depend on.
+    pub fn unload_module(&mut self, name: &str) -> Result<(), LexError> {
+        self.evalutator.unload_module(name)
+    }
+
+    /// The names of the modules loaded, in the order they were.
+    pub fn modules(&self) -> impl Iterator<Item = &str> {
+        self.evalutator.modules()
+    }
+
+    /// Defines `name` as a Rust function or closure, converting the arguments
+    /// of each call to its parameter types and its return value back to a
+    /// token:
+    ///
+    /// ```
+    /// # let mut engine = lex_lang::Engine::new();
+    /// engine.register("concat", |a: String, b: String| -> String { a + &b });
+    /// let value = engine.eval_str("(concat tea pot)")?;
+    /// assert_eq!(engine.render(&value), "(teapot)");
+    /// # Ok::<(), lex_lang::LexError>(())
+    /// ```
+    ///
+    /// `name` is read as it would be in source, so `.concat` is a keyword
+    /// and `++` an operator. A call with the wrong number of arguments fails
+    /// with [`EvalError::ArityMismatch`](crate::EvalError::ArityMismatch) and
+    /// one with an argument that doesn't convert with
+    /// [`EvalError::TypeMismatch`](crate::EvalError::TypeMismatch). A call
+    /// with an unbound variable for an argument is left as it is.
+    pub fn register<Args, F: NativeFunction<Args>>(&mut self, name: &str, f: F) {
+        let head = match self.parser.parse_with_spans(format!("({})", name)) {
+            Ok((Token::Lst(lst), _)) if lst.len() == 1 && !matches!(lst[0], Token::Lst(_)) => {
+                lst[0].clone()
+            }
+            _ => self.symbols.str(name),
+        };
+        let definition = native::definition(&self.symbols, head, f);
+        self.evalutator.add_definition(definition);
+    }
+
+    /// Rewrites the database log with only the clauses, macros and `.table`
+    /// and `.lazy` patterns defined now. Does nothing without a database.
+    pub fn compact_database(&mut self) -> Result<(), LexError> {
+        let private = self
+            .evalutator
+            .private_names()
+            .into_iter()
+            .map(Change::Private);
+        let macros = self.evalutator.macros().into_iter();
+        let tabled = self.evalutator.tabled_patterns().iter().cloned();
+        let lazy = self.evalutator.declared_lazy_patterns().iter().cloned();
+        let clauses = self.evalutator.clauses().into_iter();
+        let changes: Vec<Change> = private
+            .chain(macros.map(|(signature, template)| Change::Macro(signature, template)))
+            .chain(tabled.map(Change::Table))
+            .chain(lazy.map(Change::Lazy))
+            .chain(clauses.map(|(head, body)| Change::Assert(head, body)))
+            .collect();
+        match &mut self.journal {
+            Some(journal) => journal.compact(&changes, &self.symbols),
+            None => Ok(()),
+        }
+    }
+
+    /// Evaluates each form of `source` in turn. The result is the list of
+    /// their values, evaluated once more as a form of its own.
+    pub fn eval_str(&mut self, source: &str) -> Result<Token, LexError> {
+        let mut str = String::with_capacity(source.len() + 2);
+        str.push('(');
+        str.push_str(source);
         str.push(')');
-        str.insert(0, '(');
-        match self.parser.parse(str) {
-            Ok(ast) => match self.evalutator.eval_token(ast) {
-                Ok(token) => Ok(token),
-                Err(err) => Err(LexError::EvalError(err)),
-            },
-            Err(e) => Err(LexError::ParseError(e)),
+        let (ast, spans) = self
+            .parser
+            .parse_with_spans(str)
+            .map_err(LexError::ParseError)?;
+        let res = self
+            .evalutator
+            .eval_program(ast, spans)
+            .map_err(|err| LexError::EvalError(err, self.evalutator.take_backtrace()));
+
+        // what was defined stays defined even if the evaluation then failed
+        let changes = self.evalutator.take_changes();
+        if let Some(journal) = &mut self.journal {
+            let written = journal.append(&changes, &self.symbols);
+            if res.is_ok() {
+                written?;
+            }
         }
+
+        res
+    }
+
+    /// Runs the package in `dir`: the dependencies named by its `Lex.toml` are
+    /// found, along with theirs, so that its entry file and every file it
+    /// imports can import them by name. Dependencies are local directories,
+    /// so nothing is fetched.
+    pub fn eval_package<P: AsRef<Path>>(&mut self, dir: P) -> Result<Token, LexError> {
+        let (package, dependencies) = manifest::resolve(dir.as_ref())?;
+        for dependency in dependencies {
+            self.evalutator
+                .add_package(&dependency.name, dependency.entry);
+        }
+        self.eval_file(package.entry)
+    }
+
+    /// Evaluates the file at `path` as [`eval_str`](Engine::eval_str) would,
+    /// except that it imports relative to the file rather than to the
+    /// current directory.
+    pub fn eval_file<P: AsRef<Path>>(&mut self, path: P) -> Result<Token, LexError> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path).map_err(|err| io_error(path, err))?;
+
+        let file = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+        self.evalutator.set_file(Some(file));
+        let res = self.eval_str(&source);
+        self.evalutator.set_file(None);
+        res
+    }
+
+    /// The bindings of the variables of `pattern` for each fact it matches,
+    /// in the order the facts were given. Facts are the clauses given by
+    /// `.dec`, or by `.def` with a body of `true`.
+    pub fn query(&mut self, pattern: &str) -> Result<Vec<Bindings>, LexError> {
+        let (forms, _) = self
+            .parser
+            .parse_with_spans(format!("({})", pattern))
+            .map_err(LexError::ParseError)?;
+        let pattern = match forms {
+            Token::Lst(forms) if !forms.is_empty() => forms[0].clone(),
+            _ => return Err(LexError::ParseError(ParseError::UnknownError)),
+        };
+
+        let bindings = self
+            .evalutator
+            .clauses()
+            .into_iter()
+            .filter(|(head, body)| *body == Token::Bool(true) && match_token(&pattern, head))
+            .map(|(head, _)| Bindings {
+                values: match_variable(&pattern, &head)
+                    .into_iter()
+                    .map(|(name, value)| (self.symbols.resolve(name).to_string(), value))
+                    .collect(),
+            })
+            .collect();
+
+        Ok(bindings)
+    }
+
+    /// The definitions, tabled answers and stored values as they are now.
+    pub fn snapshot(&self) -> Snapshot {
+        self.evalutator.snapshot()
+    }
+
+    /// Puts back what [`snapshot`](Engine::snapshot) saw, dropping everything
+    /// defined or set since, including from the database.
+    pub fn restore(&mut self, snapshot: Snapshot) -> Result<(), LexError> {
+        self.evalutator.restore(snapshot);
+        self.compact_database()
+    }
+
+    /// Writes the clauses, macros and tabled patterns defined so far to an
+    /// image, along with which of the clauses are private to the files that
+    /// imported them. Nothing is written if a clause nests lists deeper than
+    /// an image can be read back with.
+    pub fn save_image<P: AsRef<Path>>(&self, path: P) -> Result<(), LexError> {
+        let path = path.as_ref();
+        let image = Image {
+            tabled: self.evalutator.tabled_patterns().to_vec(),
+            clauses: self.evalutator.clauses(),
+            index: self.evalutator.clause_layout(),
+            private: self.evalutator.private_names(),
+            macros: self.evalutator.macros(),
+        };
+        let bytes = image::encode(&image, &self.symbols)
+            .map_err(|err| LexError::Image(path.display().to_string(), err))?;
+        std::fs::write(path, bytes).map_err(|err| io_error(path, err))
+    }
+
+    /// Adds the clauses and tabled patterns of an image written by
+    /// [`save_image`](Engine::save_image) after those already defined.
+    pub fn load_image<P: AsRef<Path>>(&mut self, path: P) -> Result<(), LexError> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).map_err(|err| io_error(path, err))?;
+        let image = image::decode(&bytes, &self.symbols)
+            .map_err(|err| LexError::Image(path.display().to_string(), err))?;
+
+        for pattern in image.tabled {
+            self.evalutator.add_tabled_pattern(pattern);
+        }
+        self.evalutator.add_clauses(&image.clauses, &image.index);
+        for name in image.private {
+            self.evalutator.add_private_name(name);
+        }
+        for (signature, template) in image.macros {
+            self.evalutator.add_macro(signature, template);
+        }
+        Ok(())
+    }
+
+    /// The names of the symbols held by tokens, shared with every module.
+    pub fn symbols(&self) -> &Interner {
+        &self.symbols
+    }
+
+    /// A token written on a single line, as the source would have it.
+    pub fn render(&self, token: &Token) -> String {
+        render_token(&self.symbols, token)
+    }
+}
+
+pub(crate) fn io_error(path: &Path, err: std::io::Error) -> LexError {
+    LexError::Io(path.display().to_string(), err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{EvalError, ImageError, ModuleError};
+    use crate::evaluator::definition::Definition;
+    use crate::evaluator::EvalState;
+    use crate::symbol::Interner;
+    use crate::testing::{eval, TempDir};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    #[test]
+    fn restore_drops_later_changes() {
+        let mut engine = Engine::new();
+        eval(&mut engine, "(.dec (likes tea)) (.set mood good)");
+
+        let snapshot = engine.snapshot();
+        eval(&mut engine, "(.dec (likes coffee)) (.set mood bad)");
+        assert_eq!(
+            eval(&mut engine, "(likes coffee) (.get mood)"),
+            "(true bad)"
+        );
+
+        engine.restore(snapshot).unwrap();
+        assert_eq!(
+            eval(&mut engine, "(likes tea) (likes coffee) (.get mood)"),
+            "(true (likes coffee) good)"
+        );
+    }
+
+    #[test]
+    fn images_keep_the_index_of_their_clauses() {
+        let dir = TempDir::new("index");
+        let path = dir.join("facts.lxi");
+        let program = "(.dec (likes a tea)) (.def (likes X Y) (drinks X Y))
+                       (.dec (likes b coffee)) (.dec (edge 1 2)) (.dec done)";
+
+        let mut source = Engine::new();
+        eval(&mut source, program);
+        source.save_image(&path).unwrap();
+
+        // loaded after a clause of its own, which the image's come after
+        let mut engine = Engine::new();
+        eval(&mut engine, "(.dec (likes c tea))");
+        engine.load_image(&path).unwrap();
+
+        let mut built = Engine::new();
+        eval(&mut built, "(.dec (likes c tea))");
+        eval(&mut built, program);
+        assert_eq!(
+            engine.evalutator.clause_layout(),
+            built.evalutator.clause_layout()
+        );
+        assert_eq!(
+            eval(
+                &mut engine,
+                "(likes c tea) (likes b coffee) (likes b tea) (edge 1 2)"
+            ),
+            "(true (drinks b coffee) (drinks b tea) true)"
+        );
+    }
+
+    #[test]
+    fn images_are_not_written_deeper_than_they_can_be_read() {
+        let dir = TempDir::new("deep-image");
+        let path = dir.join("deep.lxi");
+        let deep = "(".repeat(image::MAX_NESTING) + "x" + &")".repeat(image::MAX_NESTING);
+
+        let mut engine = Engine::new();
+        eval(&mut engine, &format!("(.dec (deep {}))", deep));
+        assert!(matches!(
+            engine.save_image(&path),
+            Err(LexError::Image(_, ImageError::TooDeep { .. }))
+        ));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn queries_bind_the_variables_of_matching_facts() {
+        let mut engine = Engine::new();
+        eval(
+            &mut engine,
+            "(.dec (likes alice tea))
+             (.def (likes bob X) (drink X))
+             (.dec (likes carol coffee))",
+        );
+
+        let bindings = engine.query("(likes Who What)").unwrap();
+        let rendered: Vec<Vec<(String, String)>> = bindings
+            .iter()
+            .map(|b| {
+                b.iter()
+                    .map(|(name, value)| (name.to_owned(), engine.render(value)))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            rendered,
+            vec![
+                vec![
+                    ("What".into(), "tea".into()),
+                    ("Who".into(), "alice".into())
+                ],
+                vec![
+                    ("What".into(), "coffee".into()),
+                    ("Who".into(), "carol".into())
+                ],
+            ]
+        );
+
+        assert_eq!(engine.query("(likes alice coffee)").unwrap().len(), 0);
+        assert!(engine.query("(likes alice tea)").unwrap()[0].is_empty());
+        assert!(matches!(
+            engine.query("(likes"),
+            Err(LexError::ParseError(_))
+        ));
+    }
+
+    #[derive(Default)]
+    struct Counter {
+        calls: Arc<AtomicUsize>,
+        loaded: Arc<AtomicBool>,
+    }
+
+    impl Module for Counter {
+        fn name(&self) -> &str {
+            "counter"
+        }
+
+        fn dependencies(&self) -> &[&str] {
+            &["math"]
+        }
+
+        fn load(&self, symbols: &Interner) -> Vec<Definition> {
+            let calls = self.calls.clone();
+            vec![Definition::function(
+                crate::sig!(symbols, (.tick)),
+                move || calls.fetch_add(1, Ordering::SeqCst) + 1,
+            )]
+        }
+
+        fn on_load(&self, _: &mut EvalState) -> Result<(), EvalError> {
+            self.loaded.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn on_unload(&self, _: &mut EvalState) {
+            self.loaded.store(false, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn modules_keep_state_and_unload() {
+        let mut engine = Engine::new();
+        let counter = Counter::default();
+        let loaded = counter.loaded.clone();
+        engine.load_module(counter).unwrap();
+        assert!(loaded.load(Ordering::SeqCst));
+
+        assert_eq!(
+            eval(&mut engine, "(.tick) (.tick) (.counter/tick)"),
+            "(1 2 3)"
+        );
+        assert_eq!(
+            eval(&mut engine, "(.clauses/dec (a b)) (a b)"),
+            "(true true)"
+        );
+
+        assert!(matches!(
+            engine.load_module(Counter::default()),
+            Err(LexError::Module(ModuleError::AlreadyLoaded(_)))
+        ));
+        assert!(matches!(
+            engine.unload_module("math"),
+            Err(LexError::Module(ModuleError::RequiredBy { .. }))
+        ));
+
+        engine.unload_module("counter").unwrap();
+        assert!(!loaded.load(Ordering::SeqCst));
+        assert_eq!(eval(&mut engine, "(.tick)"), "((.tick))");
+
+        engine.unload_module("math").unwrap();
+        assert!(matches!(
+            engine.load_module(Counter::default()),
+            Err(LexError::Module(ModuleError::MissingDependency { .. }))
+        ));
+        assert!(!engine.modules().any(|name| name == "math"));
+    }
+
+    #[test]
+    fn private_definitions_stay_private_once_saved() {
+        let dir = TempDir::new("saved");
+        dir.write(
+            "arith.lx",
+            "(.defp (double X) (* X 2)) (.def (quad X) (double (double X)))",
+        );
+        let database = dir.join("facts.lxd");
+        let image = dir.join("facts.lxi");
+        let open = || Engine::with_options(EngineOptions::default().database(&database)).unwrap();
+        let check = |engine: &mut Engine| {
+            assert_eq!(eval(engine, "(arith/quad 3) (double 3)"), "(12 (double 3))");
+            let res = eval(engine, "(arith/double 3)");
+            assert!(res.starts_with("arith/double is private"), "{}", res);
+        };
+
+        let mut engine = open();
+        eval(
+            &mut engine,
+            &format!("(.import \"{}/arith\")", dir.path().display()),
+        );
+        drop(engine);
+
+        let mut engine = open();
+        check(&mut engine);
+        engine.compact_database().unwrap();
+        drop(engine);
+
+        let mut engine = open();
+        check(&mut engine);
+        engine.save_image(&image).unwrap();
+
+        let mut engine = Engine::new();
+        engine.load_image(&image).unwrap();
+        check(&mut engine);
+    }
+
+    #[test]
+    fn macros_are_kept_with_the_clauses() {
+        let dir = TempDir::new("macros");
+        let database = dir.join("facts.lxd");
+        let image = dir.join("facts.lxi");
+        let open = || Engine::with_options(EngineOptions::default().database(&database)).unwrap();
+
+        let mut engine = open();
+        eval(
+            &mut engine,
+            "(.defmacro (twice X) `(* ,X 2)) (.def (f X) (twice X))",
+        );
+        drop(engine);
+
+        let mut engine = open();
+        assert_eq!(eval(&mut engine, "(f 3)"), "(6)");
+        engine.compact_database().unwrap();
+        drop(engine);
+
+        let mut engine = open();
+        assert_eq!(eval(&mut engine, "(f 3)"), "(6)");
+        engine.save_image(&image).unwrap();
+
+        let mut engine = Engine::new();
+        engine.load_image(&image).unwrap();
+        assert_eq!(eval(&mut engine, "(f 3)"), "(6)");
     }
 }
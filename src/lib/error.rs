@@ -1,26 +1,52 @@
+use crate::evaluator::trace::Backtrace;
+use crate::symbol::{sym, Interner};
+use crate::token::Token;
+
 use std::fmt;
+use std::time::Duration;
 
+/// Why parsing or evaluating source, or reading the files an engine uses,
+/// failed.
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum LexError {
     ParseError(ParseError),
-    EvalError(EvalError),
+    EvalError(EvalError, Backtrace),
+    Io(String, String),
+    Image(String, ImageError),
+    Database(String, DatabaseError),
+    Module(ModuleError),
+    Manifest(String, ManifestError),
 }
 
 impl fmt::Display for LexError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             LexError::ParseError(err) => err.fmt(f),
-            LexError::EvalError(err) => err.fmt(f),
+            LexError::EvalError(err, backtrace) => {
+                err.fmt(f)?;
+                if !backtrace.is_empty() {
+                    write!(f, "\n{}", backtrace)?;
+                }
+                Ok(())
+            }
+            LexError::Io(path, msg) => write!(f, "cannot open \"{}\": {}", path, msg),
+            LexError::Image(path, err) => write!(f, "cannot load image \"{}\": {}", path, err),
+            LexError::Database(path, err) => write!(f, "database \"{}\": {}", path, err),
+            LexError::Module(err) => err.fmt(f),
+            LexError::Manifest(path, err) => write!(f, "invalid manifest \"{}\": {}", path, err),
         }
     }
 }
 
+/// Why source couldn't be parsed, with the line and column where it was
+/// noticed.
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum ParseError {
     InvalidCharacter(char, i32, i32),
     InvalidSymbol(String, i32, i32),
     MissingBracket(i32),
-    Other(i32),
     UnknownError,
 }
 
@@ -32,20 +58,331 @@ impl fmt::Display for ParseError {
                 "invalid character \"{}\" at line {}:{}",
                 c, line, collumn
             ),
-            _ => write!(f, "unknown parse error"),
+            ParseError::InvalidSymbol(sym, line, collumn) => {
+                write!(f, "invalid symbol \"{}\" at line {}:{}", sym, line, collumn)
+            }
+            ParseError::MissingBracket(line) => write!(f, "missing bracket at line {}", line),
+            ParseError::UnknownError => write!(f, "unknown parse error"),
+        }
+    }
+}
+
+/// Why a module couldn't be loaded or unloaded.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum ModuleError {
+    AlreadyLoaded(String),
+    NotLoaded(String),
+    MissingDependency { module: String, dependency: String },
+    RequiredBy { module: String, by: String },
+}
+
+impl fmt::Display for ModuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModuleError::AlreadyLoaded(module) => {
+                write!(f, "module \"{}\" is already loaded", module)
+            }
+            ModuleError::NotLoaded(module) => write!(f, "module \"{}\" isn't loaded", module),
+            ModuleError::MissingDependency { module, dependency } => write!(
+                f,
+                "module \"{}\" depends on \"{}\", which isn't loaded",
+                module, dependency
+            ),
+            ModuleError::RequiredBy { module, by } => {
+                write!(f, "module \"{}\" is still needed by \"{}\"", module, by)
+            }
+        }
+    }
+}
+
+/// Why the `Lex.toml` of a package or of one of its dependencies couldn't be
+/// used.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum ManifestError {
+    Syntax {
+        line: usize,
+        reason: String,
+    },
+    MissingKey(String),
+    UnknownKey(String),
+    InvalidValue {
+        key: String,
+        expected: String,
+    },
+    DependencyCycle(Vec<String>),
+    ConflictingDependency {
+        name: String,
+        first: String,
+        second: String,
+    },
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ManifestError::Syntax { line, reason } => write!(f, "{} at line {}", reason, line),
+            ManifestError::MissingKey(key) => write!(f, "missing {}", key),
+            ManifestError::UnknownKey(key) => write!(f, "unknown key {}", key),
+            ManifestError::InvalidValue { key, expected } => {
+                write!(f, "{} should be {}", key, expected)
+            }
+            ManifestError::DependencyCycle(cycle) => {
+                write!(f, "dependency cycle {}", cycle.join(" -> "))
+            }
+            ManifestError::ConflictingDependency {
+                name,
+                first,
+                second,
+            } => write!(f, "package \"{}\" is both {} and {}", name, first, second),
+        }
+    }
+}
+
+/// Why an image couldn't be read or written.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum ImageError {
+    NotAnImage,
+    UnsupportedVersion { found: u32, expected: u32 },
+    ChecksumMismatch,
+    Truncated,
+    InvalidTag(u8),
+    InvalidSymbol(u32),
+    InvalidName,
+    // the index doesn't hold each clause once
+    InvalidIndex,
+    TooDeep { limit: usize },
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImageError::NotAnImage => write!(f, "not a file written by lex"),
+            ImageError::UnsupportedVersion { found, expected } => write!(
+                f,
+                "image format version {} is not supported, expected {}",
+                found, expected
+            ),
+            ImageError::ChecksumMismatch => write!(f, "checksum mismatch, the image is corrupt"),
+            ImageError::Truncated => write!(f, "unexpected end of image"),
+            ImageError::InvalidTag(tag) => write!(f, "invalid token tag {}", tag),
+            ImageError::InvalidSymbol(id) => write!(f, "invalid symbol {}", id),
+            ImageError::InvalidName => write!(f, "symbol name is not valid utf-8"),
+            ImageError::InvalidIndex => write!(f, "the index doesn't match the clauses"),
+            ImageError::TooDeep { limit } => {
+                write!(f, "lists are nested deeper than {}", limit)
+            }
         }
     }
 }
 
+/// Why a database log couldn't be replayed, or a change written to it.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum DatabaseError {
+    NotADatabase,
+    UnsupportedVersion { found: u32, expected: u32 },
+    ChecksumMismatch,
+    // the record at this position, counting from 1, can't be read
+    InvalidRecord(usize),
+    TooDeep { limit: usize },
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DatabaseError::NotADatabase => write!(f, "not a database log written by lex"),
+            DatabaseError::UnsupportedVersion { found, expected } => write!(
+                f,
+                "log format version {} is not supported, expected {}",
+                found, expected
+            ),
+            DatabaseError::ChecksumMismatch => write!(f, "checksum mismatch, the log is corrupt"),
+            DatabaseError::InvalidRecord(record) => write!(f, "record {} is not valid", record),
+            DatabaseError::TooDeep { limit } => write!(
+                f,
+                "a change nests lists deeper than {}, so none of its evaluation was logged",
+                limit
+            ),
+        }
+    }
+}
+
+/// Why an evaluation failed. Definitions and inputs are rendered tokens, e.g.
+/// `(fib N)` and `(fib 10)`.
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum EvalError {
-    UnknownOperator,
+    UnboundVariable {
+        name: String,
+        definition: String,
+    },
+    ArityMismatch {
+        definition: String,
+        expected: Vec<usize>,
+        found: usize,
+    },
+    TypeMismatch {
+        definition: String,
+        expected: String,
+        found: String,
+    },
+    DivisionByZero {
+        definition: String,
+        input: String,
+    },
+    NoMatchingDefinition {
+        definition: String,
+        input: String,
+    },
+    // the value no arm of a .match-case matched
+    NoMatchingCase {
+        input: String,
+    },
+    StackOverflow {
+        limit: usize,
+    },
+    StepLimitExceeded {
+        limit: u64,
+    },
+    Timeout {
+        timeout: Duration,
+    },
+    Cancelled,
+    // raised by .throw, holds the thrown value and its rendering
+    Thrown(Token, String),
+    // searched holds the directories looked in
+    ModuleNotFound {
+        module: String,
+        searched: Vec<String>,
+    },
+    // the files importing each other, ending with the one imported again
+    ImportCycle {
+        cycle: Vec<String>,
+    },
+    ImportFailed {
+        module: String,
+        reason: String,
+    },
+    PrivateDefinition {
+        definition: String,
+        module: String,
+    },
+}
+
+impl EvalError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            EvalError::UnboundVariable { .. } => "unbound-variable",
+            EvalError::ArityMismatch { .. } => "arity-mismatch",
+            EvalError::TypeMismatch { .. } => "type-mismatch",
+            EvalError::DivisionByZero { .. } => "division-by-zero",
+            EvalError::NoMatchingDefinition { .. } => "no-matching-definition",
+            EvalError::NoMatchingCase { .. } => "no-matching-case",
+            EvalError::StackOverflow { .. } => "stack-overflow",
+            EvalError::StepLimitExceeded { .. } => "step-limit-exceeded",
+            EvalError::Timeout { .. } => "timeout",
+            EvalError::Cancelled => "cancelled",
+            EvalError::Thrown(..) => "thrown",
+            EvalError::ModuleNotFound { .. } => "module-not-found",
+            EvalError::ImportCycle { .. } => "import-cycle",
+            EvalError::ImportFailed { .. } => "import-failed",
+            EvalError::PrivateDefinition { .. } => "private-definition",
+        }
+    }
+
+    // running out of budget stops the whole evaluation, .try can't catch it
+    pub fn is_catchable(&self) -> bool {
+        !matches!(
+            self,
+            EvalError::StepLimitExceeded { .. } | EvalError::Timeout { .. } | EvalError::Cancelled
+        )
+    }
+
+    // the value seen by a .catch clause: the thrown value itself, or
+    // (.error kind "message") for errors raised by the evaluator
+    pub fn to_token(&self, symbols: &Interner) -> Token {
+        match self {
+            EvalError::Thrown(token, _) => token.clone(),
+            err => Token::list(vec![
+                Token::Keyword(sym::ERROR),
+                symbols.str(err.kind()),
+                symbols.str(&err.to_string()),
+            ]),
+        }
+    }
 }
 
 impl fmt::Display for EvalError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            _ => write!(f, "unknown error"),
+            EvalError::UnboundVariable { name, definition } => write!(
+                f,
+                "unbound variable \"{}\" in definition {}",
+                name, definition
+            ),
+            EvalError::ArityMismatch {
+                definition,
+                expected,
+                found,
+            } => {
+                let expected: Vec<String> = expected.iter().map(|n| n.to_string()).collect();
+                write!(
+                    f,
+                    "{} expects {} argument(s), found {}",
+                    definition,
+                    expected.join(" or "),
+                    found
+                )
+            }
+            EvalError::TypeMismatch {
+                definition,
+                expected,
+                found,
+            } => write!(
+                f,
+                "type mismatch in {}: expected {}, found {}",
+                definition, expected, found
+            ),
+            EvalError::DivisionByZero { definition, input } => {
+                write!(
+                    f,
+                    "division by zero in {} while evaluating {}",
+                    definition, input
+                )
+            }
+            EvalError::NoMatchingDefinition { definition, input } => {
+                write!(f, "no definition of {} matches {}", definition, input)
+            }
+            EvalError::NoMatchingCase { input } => {
+                write!(f, "no case of .match-case matches {}", input)
+            }
+            EvalError::StackOverflow { limit } => {
+                write!(f, "stack overflow, evaluation nested deeper than {}", limit)
+            }
+            EvalError::StepLimitExceeded { limit } => {
+                write!(f, "evaluation exceeded the limit of {} steps", limit)
+            }
+            EvalError::Timeout { timeout } => {
+                write!(f, "evaluation timed out after {:?}", timeout)
+            }
+            EvalError::Cancelled => write!(f, "evaluation cancelled"),
+            EvalError::Thrown(_, rendered) => write!(f, "uncaught exception {}", rendered),
+            EvalError::ModuleNotFound { module, searched } => write!(
+                f,
+                "module \"{}\" not found in {}",
+                module,
+                searched.join(", ")
+            ),
+            EvalError::ImportCycle { cycle } => write!(f, "import cycle {}", cycle.join(" -> ")),
+            EvalError::ImportFailed { module, reason } => {
+                write!(f, "cannot import \"{}\": {}", module, reason)
+            }
+            EvalError::PrivateDefinition { definition, module } => {
+                write!(f, "{} is private to module \"{}\"", definition, module)
+            }
         }
     }
 }
@@ -0,0 +1,148 @@
+use crate::error::EvalError;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Lets another thread stop an evaluation, which then fails with
+/// [`EvalError::Cancelled`]. The request is consumed by the evaluation it
+/// stops, and one made while no evaluation runs is dropped when the next
+/// one starts.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    /// Stops the evaluation in progress.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    fn reset(&self) {
+        self.cancelled.store(false, Ordering::SeqCst);
+    }
+
+    fn take(&self) -> bool {
+        self.cancelled.swap(false, Ordering::SeqCst)
+    }
+}
+
+// limits on a single call to Evaluator::eval_program
+#[derive(Debug, Clone, Default)]
+pub struct Budget {
+    pub max_steps: Option<u64>,
+    pub timeout: Option<Duration>,
+    pub cancellation: CancellationToken,
+    steps: u64,
+    deadline: Option<Instant>,
+}
+
+impl Budget {
+    pub fn start(&mut self) {
+        self.steps = 0;
+        self.deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        self.cancellation.reset();
+    }
+
+    // called once per reduction, i.e. every time a definition is run
+    pub fn step(&mut self) -> Result<(), EvalError> {
+        self.steps += 1;
+
+        if let Some(limit) = self.max_steps {
+            if self.steps > limit {
+                return Err(EvalError::StepLimitExceeded { limit });
+            }
+        }
+
+        if let (Some(deadline), Some(timeout)) = (self.deadline, self.timeout) {
+            if Instant::now() >= deadline {
+                return Err(EvalError::Timeout { timeout });
+            }
+        }
+
+        if self.cancellation.take() {
+            return Err(EvalError::Cancelled);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{Engine, EngineOptions};
+    use crate::error::LexError;
+
+    const LOOP: &str = "(.def (f N) (f N)) (f 1)";
+
+    fn eval_err(engine: &mut Engine, program: &str) -> EvalError {
+        match engine.eval_str(program) {
+            Err(LexError::EvalError(err, _)) => err,
+            res => panic!("expected an error from {}, got {:?}", program, res),
+        }
+    }
+
+    // the engine still evaluates once a limit has stopped it
+    fn assert_usable(engine: &mut Engine) {
+        let value = engine.eval_str("(+ 1 2)").unwrap();
+        assert_eq!(engine.render(&value), "(3)");
+    }
+
+    #[test]
+    fn each_limit_has_its_own_error() {
+        let mut engine = Engine::with_options(EngineOptions {
+            max_steps: Some(1000),
+            ..EngineOptions::default()
+        })
+        .unwrap();
+        assert!(matches!(
+            eval_err(&mut engine, LOOP),
+            EvalError::StepLimitExceeded { limit: 1000 }
+        ));
+        assert_usable(&mut engine);
+
+        let mut engine = Engine::with_options(EngineOptions {
+            timeout: Some(Duration::from_millis(20)),
+            ..EngineOptions::default()
+        })
+        .unwrap();
+        assert!(matches!(
+            eval_err(&mut engine, LOOP),
+            EvalError::Timeout { .. }
+        ));
+        assert_usable(&mut engine);
+
+        let cancellation = CancellationToken::new();
+        let mut engine = Engine::with_options(EngineOptions {
+            cancellation: cancellation.clone(),
+            ..EngineOptions::default()
+        })
+        .unwrap();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            cancellation.cancel();
+        });
+        assert!(matches!(eval_err(&mut engine, LOOP), EvalError::Cancelled));
+        canceller.join().unwrap();
+        assert_usable(&mut engine);
+    }
+
+    #[test]
+    fn cancelling_between_evaluations_stops_nothing() {
+        let cancellation = CancellationToken::new();
+        let mut engine = Engine::with_options(EngineOptions {
+            cancellation: cancellation.clone(),
+            ..EngineOptions::default()
+        })
+        .unwrap();
+
+        cancellation.cancel();
+        assert_usable(&mut engine);
+    }
+}
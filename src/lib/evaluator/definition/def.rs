@@ -1,79 +1,191 @@
-use crate::lib::evaluator::definition::prelude::*;
+use crate::evaluator::definition::prelude::*;
+
+use crate::database::Change;
+use crate::evaluator::definition::Handler;
+use crate::evaluator::vm::Clause;
 
 use std::collections::HashMap;
 
 pub struct Def;
 
 impl Module for Def {
-    fn load() -> Vec<Definition> {
+    fn name(&self) -> &str {
+        "clauses"
+    }
+
+    fn load(&self, symbols: &Interner) -> Vec<Definition> {
         vec![
-            Definition::new(def_sig(), handler!(def_handler)),
-            Definition::new(dec_sig(), handler!(dec_handler)),
+            Definition::special(
+                sig!(symbols, (.def Definition Result)),
+                handler!(def_handler),
+            ),
+            // private to the file defining it once imported, and otherwise .def
+            Definition::special(
+                sig!(symbols, (.defp Definition Result)),
+                handler!(def_handler),
+            ),
+            Definition::special(sig!(symbols, (.dec Declaration)), handler!(dec_handler)),
+            // read by .import, the names of a file that aren't private
+            Definition::special(sig!(symbols, (.export Names)), handler!(export_handler)),
+            Definition::special(sig!(symbols, (.retract Pattern)), handler!(retract_handler)),
+            Definition::special(sig!(symbols, (.lazy Pattern)), handler!(lazy_handler)),
+            Definition::special(
+                sig!(symbols, (.defmacro Signature Template)),
+                handler!(defmacro_handler),
+            ),
         ]
     }
 }
 
-fn def_sig() -> Token {
-    Token::Lst(vec![
-        Token::Keyword(".def".to_owned()),
-        Token::Var("Definition".to_owned()),
-        Token::Var("Result".to_owned()),
-    ])
-}
-
-fn dec_sig() -> Token {
-    Token::Lst(vec![
-        Token::Keyword(".dec".to_owned()),
-        Token::Var("Declaration".to_owned()),
-    ])
+pub fn match_variable(sig: &Token, value: &Token) -> HashMap<Symbol, Token> {
+    let mut hm: HashMap<Symbol, Token> = HashMap::new();
+    bind_variables(sig, value, &mut hm);
+    hm
 }
 
-fn match_variable(sig: Token, value: Token) -> HashMap<String, Token> {
-    let mut hm: HashMap<String, Token> = HashMap::new();
+fn bind_variables(sig: &Token, value: &Token, hm: &mut HashMap<Symbol, Token>) {
     match (sig, value) {
         (Token::Var(s), v) => {
-            hm.insert(s, v);
+            hm.insert(*s, v.clone());
         }
         (Token::Lst(nsig), Token::Lst(nval)) => {
-            for i in 0..nsig.len() {
-                let nhm = match_variable(nsig[i].clone(), nval[i].clone());
-                hm.extend(nhm);
+            for (s, v) in nsig.iter().zip(nval.iter()) {
+                bind_variables(s, v, hm);
             }
         }
         _ => (),
     }
+}
 
-    hm
+pub fn variable_names(token: &Token) -> Vec<Symbol> {
+    match token {
+        Token::Var(s) => vec![*s],
+        Token::Lst(lst) => lst.iter().flat_map(variable_names).collect(),
+        _ => vec![],
+    }
 }
 
-fn replace_variable(token: Token, variables: HashMap<String, Token>) -> Token {
+pub fn replace_variable(
+    token: &Token,
+    variables: &HashMap<Symbol, Token>,
+) -> Result<Token, Symbol> {
     match token {
-        Token::Var(s) => variables.get(&s).unwrap().clone(),
+        Token::Var(s) => match variables.get(s) {
+            Some(value) => Ok(value.clone()),
+            None => Err(*s),
+        },
         Token::Lst(lst) => {
-            let ret: Vec<Token> = lst
-                .into_iter()
-                .map(move |token| replace_variable(token, variables.clone()))
-                .collect();
+            if let [Token::Keyword(keyword), _, _] = &**lst {
+                if *keyword == sym::CATCH {
+                    return replace_in_scope(lst, 1, variables);
+                }
+            }
+            match &**lst {
+                [Token::Keyword(sym::QUOTE), _] => return Ok(token.clone()),
+                [Token::Keyword(sym::QUASIQUOTE), template] => {
+                    let template = replace_unquoted(template, variables)?;
+                    return Ok(Token::list(vec![lst[0].clone(), template]));
+                }
+                [Token::Keyword(sym::MATCH_CASE), expr, arms @ ..] => {
+                    let mut ret = vec![lst[0].clone(), replace_variable(expr, variables)?];
+                    for arm in arms {
+                        ret.push(match arm {
+                            Token::Lst(case) if case.len() == 2 => {
+                                replace_in_scope(case, 0, variables)?
+                            }
+                            arm => replace_variable(arm, variables)?,
+                        });
+                    }
+                    return Ok(Token::list(ret));
+                }
+                _ => {}
+            }
 
-            Token::Lst(ret)
+            let ret = lst
+                .iter()
+                .map(|token| replace_variable(token, variables))
+                .collect::<Result<Vec<Token>, Symbol>>()?;
+
+            Ok(Token::list(ret))
         }
-        t => t,
+        t => Ok(t.clone()),
     }
 }
 
+// the variables of a quoted form are data, only those of the forms unquoted
+// in it belong to the clause
+fn replace_unquoted(template: &Token, variables: &HashMap<Symbol, Token>) -> Result<Token, Symbol> {
+    match template {
+        Token::Lst(lst) => match &**lst {
+            [Token::Keyword(sym::UNQUOTE | sym::SPLICE), _] => {
+                replace_variable(template, variables)
+            }
+            _ => Ok(Token::list(
+                lst.iter()
+                    .map(|item| replace_unquoted(item, variables))
+                    .collect::<Result<Vec<Token>, Symbol>>()?,
+            )),
+        },
+        atom => Ok(atom.clone()),
+    }
+}
+
+// the variables of the pattern at lst[at], that of a (.catch Pattern handler)
+// or of a (Pattern result) arm of .match-case, are bound by the form itself,
+// so they shadow the outer ones and are left for it to replace
+fn replace_in_scope(
+    lst: &[Token],
+    at: usize,
+    variables: &HashMap<Symbol, Token>,
+) -> Result<Token, Symbol> {
+    let mut variables = variables.clone();
+    for name in variable_names(&lst[at]) {
+        variables.insert(name, Token::Var(name));
+    }
+
+    let ret = lst
+        .iter()
+        .enumerate()
+        .map(|(i, token)| match i {
+            i if i == at => Ok(token.clone()),
+            _ => replace_variable(token, &variables),
+        })
+        .collect::<Result<Vec<Token>, Symbol>>()?;
+
+    Ok(Token::list(ret))
+}
+
+// a clause (.def head body)
+pub fn rule(head: &Token, body: &Token, compile: bool) -> Definition {
+    let param = head.clone();
+    let result = body.clone();
+    let handler: Handler = handler!(move |state: &mut EvalState, token| {
+        let variables = match_variable(&param, &token);
+        replace_variable(&result, &variables).map_err(|name| EvalError::UnboundVariable {
+            name: state.symbols.resolve(name).to_string(),
+            definition: render_token(&state.symbols, &param),
+        })
+    });
+
+    let clause = compile.then(|| Clause::compile(head, body));
+    Definition::rule(head.clone(), body.clone(), handler, clause)
+}
+
+// a clause (.dec head), which is (.def head true) without the need to bind
+// any variable
+pub fn fact(head: &Token, compile: bool) -> Definition {
+    let handler: Handler = handler!(move |_, _| { Ok(Token::Bool(true)) });
+
+    let clause = compile.then(|| Clause::compile(head, &Token::Bool(true)));
+    Definition::rule(head.clone(), Token::Bool(true), handler, clause)
+}
+
 fn def_handler(state: &mut EvalState, token: Token) -> EvalResult {
     if let Token::Lst(lst) = token {
-        if let [_, a, b] = &*lst {
-            let param = a.clone();
-            let result = b.clone();
-            state.definition.push(Definition::new(
-                param.clone(),
-                handler!(move |_, token| {
-                    let variables = match_variable(param.clone(), token);
-                    let ret = replace_variable(result.clone(), variables);
-                    Ok(ret)
-                }),
-            ));
+        if let [_, head, body] = &*lst {
+            let definition = rule(head, body, state.compile);
+            state.add_definition(definition);
+            state.record(Change::Assert(head.clone(), body.clone()));
 
             return Ok(Token::Bool(true));
         }
@@ -84,12 +196,10 @@ fn def_handler(state: &mut EvalState, token: Token) -> EvalResult {
 
 fn dec_handler(state: &mut EvalState, token: Token) -> EvalResult {
     if let Token::Lst(lst) = token {
-        if let [_, a] = &*lst {
-            let param = a.clone();
-            state.definition.push(Definition::new(
-                param,
-                handler!(move |_, _| { Ok(Token::Bool(true)) }),
-            ));
+        if let [_, head] = &*lst {
+            let definition = fact(head, state.compile);
+            state.add_definition(definition);
+            state.record(Change::Assert(head.clone(), Token::Bool(true)));
 
             return Ok(Token::Bool(true));
         }
@@ -97,3 +207,77 @@ fn dec_handler(state: &mut EvalState, token: Token) -> EvalResult {
 
     Ok(Token::Bool(false))
 }
+
+fn export_handler(_: &mut EvalState, _: Token) -> EvalResult {
+    Ok(Token::Bool(true))
+}
+
+// (.lazy (if-true C X)) leaves the arguments of the calls matching
+// (if-true C X) for the clauses to evaluate once they need them
+fn lazy_handler(state: &mut EvalState, token: Token) -> EvalResult {
+    if let Token::Lst(lst) = token {
+        if let [_, pattern] = &*lst {
+            state.declare_lazy(pattern.clone());
+            state.record(Change::Lazy(pattern.clone()));
+            return Ok(Token::Bool(true));
+        }
+    }
+
+    Ok(Token::Bool(false))
+}
+
+fn defmacro_handler(state: &mut EvalState, token: Token) -> EvalResult {
+    if let Token::Lst(lst) = token {
+        if let [_, signature, template] = &*lst {
+            let defined = state.macros.define(signature.clone(), template.clone());
+            if defined {
+                state.record(Change::Macro(signature.clone(), template.clone()));
+            }
+            return Ok(Token::Bool(defined));
+        }
+    }
+
+    Ok(Token::Bool(false))
+}
+
+// (.retract (likes bob X)) removes the clauses given by .def and .dec whose
+// head matches (likes bob X), and is false if there were none
+fn retract_handler(state: &mut EvalState, token: Token) -> EvalResult {
+    if let Token::Lst(lst) = token {
+        if let [_, pattern] = &*lst {
+            if !state.retract(pattern) {
+                return Ok(Token::Bool(false));
+            }
+            state.record(Change::Retract(pattern.clone()));
+
+            return Ok(Token::Bool(true));
+        }
+    }
+
+    Ok(Token::Bool(false))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+    use crate::testing::eval;
+
+    #[test]
+    fn clauses_are_kept_as_written() {
+        let mut engine = Engine::new();
+        eval(
+            &mut engine,
+            "(.dec (buy apple)) (.def (buy X) false) (.def (checkout X) (buy X))",
+        );
+
+        // the body of checkout is only evaluated once it is called, rather
+        // than as (buy X) when it is defined, which gave false for anything
+        assert_eq!(
+            eval(
+                &mut engine,
+                "(checkout grape) (checkout apple) (checkout Y)"
+            ),
+            "(false true false)"
+        );
+    }
+}
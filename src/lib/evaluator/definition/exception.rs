@@ -0,0 +1,58 @@
+use crate::evaluator::definition::prelude::*;
+
+pub struct Exception;
+
+impl Module for Exception {
+    fn name(&self) -> &str {
+        "exception"
+    }
+
+    fn load(&self, symbols: &Interner) -> Vec<Definition> {
+        vec![Definition::new(
+            sig!(symbols, (.throw Any)),
+            handler!(throw_handler),
+        )]
+    }
+}
+
+fn throw_handler(state: &mut EvalState, token: Token) -> EvalResult {
+    if let Token::Lst(lst) = token {
+        if let [_, t] = &*lst {
+            return Err(EvalError::Thrown(
+                t.clone(),
+                render_token(&state.symbols, t),
+            ));
+        }
+    }
+
+    Ok(Token::Bool(false))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+    use crate::testing::eval;
+
+    #[test]
+    fn clauses_catch_what_they_throw() {
+        let mut engine = Engine::new();
+        eval(
+            &mut engine,
+            "(.def (price tea) 3)
+             (.def (price P) (.throw (unknown-product P)))
+             (.def (safe-price P) (.try (price P) (.catch (unknown-product Q) (none Q))))",
+        );
+
+        assert_eq!(
+            eval(&mut engine, "(safe-price tea) (safe-price cake)"),
+            "(3 (none cake))"
+        );
+        assert_eq!(
+            eval(
+                &mut engine,
+                "(.try (/ 1 0) (.catch (.error Kind Msg) Kind))"
+            ),
+            "(division-by-zero)"
+        );
+    }
+}
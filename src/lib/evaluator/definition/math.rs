@@ -1,123 +1,103 @@
-use crate::lib::evaluator::definition::prelude::*;
+use crate::evaluator::definition::prelude::*;
 
 pub struct Math;
 
 impl Module for Math {
-    fn load() -> Vec<Definition> {
+    fn name(&self) -> &str {
+        "math"
+    }
+
+    fn load(&self, symbols: &Interner) -> Vec<Definition> {
         vec![
-            Definition::new(add_sig(), handler!(add_handler)),
-            Definition::new(sub_sig(), handler!(sub_handler)),
-            Definition::new(mul_sig(), handler!(mul_handler)),
-            Definition::new(div_sig(), handler!(div_handler)),
-            Definition::new(mod_sig(), handler!(mod_handler)),
-            Definition::new(pow_sig(), handler!(pow_handler)),
+            Definition::new(sig!(symbols, (+ Definition Result)), handler!(add_handler)),
+            Definition::new(sig!(symbols, (- Definition Result)), handler!(sub_handler)),
+            Definition::new(sig!(symbols, (* Definition Result)), handler!(mul_handler)),
+            Definition::new(sig!(symbols, (/ Definition Result)), handler!(div_handler)),
+            Definition::new(sig!(symbols, (% Definition Result)), handler!(mod_handler)),
+            Definition::new(sig!(symbols, (** Definition Result)), handler!(pow_handler)),
         ]
     }
 }
 
-fn add_sig() -> Token {
-    Token::Lst(vec![
-        Token::BinaryOp("+".to_owned()),
-        Token::Var("Definition".to_owned()),
-        Token::Var("Result".to_owned()),
-    ])
-}
-
-fn sub_sig() -> Token {
-    Token::Lst(vec![
-        Token::BinaryOp("-".to_owned()),
-        Token::Var("Definition".to_owned()),
-        Token::Var("Result".to_owned()),
-    ])
-}
-
-fn mul_sig() -> Token {
-    Token::Lst(vec![
-        Token::BinaryOp("*".to_owned()),
-        Token::Var("Definition".to_owned()),
-        Token::Var("Result".to_owned()),
-    ])
-}
+// both operands must be numbers; unbound variables and unevaluated lists are
+// left as they are so the expression can still be used inside a definition
+fn operands(symbols: &Interner, token: &Token) -> Result<Option<(f32, f32)>, EvalError> {
+    if let Token::Lst(lst) = token {
+        if let [op, a, b] = &**lst {
+            return match (a, b) {
+                (Token::Number(a), Token::Number(b)) => Ok(Some((*a, *b))),
+                (Token::Var(_) | Token::Wildcard(_) | Token::Lst(_), _)
+                | (_, Token::Var(_) | Token::Wildcard(_) | Token::Lst(_)) => Ok(None),
+                (Token::Number(_), found) | (found, _) => Err(EvalError::TypeMismatch {
+                    definition: render_token(symbols, op),
+                    expected: "number".to_owned(),
+                    found: render_token(symbols, found),
+                }),
+            };
+        }
+    }
 
-fn div_sig() -> Token {
-    Token::Lst(vec![
-        Token::BinaryOp("/".to_owned()),
-        Token::Var("Definition".to_owned()),
-        Token::Var("Result".to_owned()),
-    ])
+    Ok(None)
 }
 
-fn mod_sig() -> Token {
-    Token::Lst(vec![
-        Token::BinaryOp("%".to_owned()),
-        Token::Var("Definition".to_owned()),
-        Token::Var("Result".to_owned()),
-    ])
-}
+fn check_divisor(symbols: &Interner, token: &Token, b: f32) -> Result<(), EvalError> {
+    if b == 0.0 {
+        if let Token::Lst(lst) = token {
+            return Err(EvalError::DivisionByZero {
+                definition: render_token(symbols, &lst[0]),
+                input: render_token(symbols, token),
+            });
+        }
+    }
 
-fn pow_sig() -> Token {
-    Token::Lst(vec![
-        Token::BinaryOp("**".to_owned()),
-        Token::Var("Definition".to_owned()),
-        Token::Var("Result".to_owned()),
-    ])
+    Ok(())
 }
 
-fn add_handler(_: &mut EvalState, token: Token) -> EvalResult {
-    if let Token::Lst(lst) = token {
-        if let [_, Token::Number(a), Token::Number(b)] = &*lst {
-            return Ok(Token::Number(a + b));
-        }
+fn add_handler(state: &mut EvalState, token: Token) -> EvalResult {
+    if let Some((a, b)) = operands(&state.symbols, &token)? {
+        return Ok(Token::Number(a + b));
     }
 
     Ok(Token::Unknown)
 }
 
-fn sub_handler(_: &mut EvalState, token: Token) -> EvalResult {
-    if let Token::Lst(lst) = token {
-        if let [_, Token::Number(a), Token::Number(b)] = &*lst {
-            return Ok(Token::Number(a - b));
-        }
+fn sub_handler(state: &mut EvalState, token: Token) -> EvalResult {
+    if let Some((a, b)) = operands(&state.symbols, &token)? {
+        return Ok(Token::Number(a - b));
     }
 
     Ok(Token::Unknown)
 }
 
-fn mul_handler(_: &mut EvalState, token: Token) -> EvalResult {
-    if let Token::Lst(lst) = token {
-        if let [_, Token::Number(a), Token::Number(b)] = &*lst {
-            return Ok(Token::Number(a * b));
-        }
+fn mul_handler(state: &mut EvalState, token: Token) -> EvalResult {
+    if let Some((a, b)) = operands(&state.symbols, &token)? {
+        return Ok(Token::Number(a * b));
     }
 
     Ok(Token::Unknown)
 }
 
-fn div_handler(_: &mut EvalState, token: Token) -> EvalResult {
-    if let Token::Lst(lst) = token {
-        if let [_, Token::Number(a), Token::Number(b)] = &*lst {
-            return Ok(Token::Number(a / b));
-        }
+fn div_handler(state: &mut EvalState, token: Token) -> EvalResult {
+    if let Some((a, b)) = operands(&state.symbols, &token)? {
+        check_divisor(&state.symbols, &token, b)?;
+        return Ok(Token::Number(a / b));
     }
 
     Ok(Token::Unknown)
 }
 
-fn mod_handler(_: &mut EvalState, token: Token) -> EvalResult {
-    if let Token::Lst(lst) = token {
-        if let [_, Token::Number(a), Token::Number(b)] = &*lst {
-            return Ok(Token::Number(a % b));
-        }
+fn mod_handler(state: &mut EvalState, token: Token) -> EvalResult {
+    if let Some((a, b)) = operands(&state.symbols, &token)? {
+        check_divisor(&state.symbols, &token, b)?;
+        return Ok(Token::Number(a % b));
     }
 
     Ok(Token::Unknown)
 }
 
-fn pow_handler(_: &mut EvalState, token: Token) -> EvalResult {
-    if let Token::Lst(lst) = token {
-        if let [_, Token::Number(a), Token::Number(b)] = &*lst {
-            return Ok(Token::Number(f32::powf(*a, *b)));
-        }
+fn pow_handler(state: &mut EvalState, token: Token) -> EvalResult {
+    if let Some((a, b)) = operands(&state.symbols, &token)? {
+        return Ok(Token::Number(f32::powf(a, b)));
     }
 
     Ok(Token::Unknown)
@@ -1,35 +1,26 @@
-use crate::lib::evaluator::definition::prelude::*;
-use crate::lib::printer::print_token;
+use crate::evaluator::definition::prelude::*;
+use crate::printer::print_token;
 
 pub struct Misc;
 
 impl Module for Misc {
-    fn load() -> Vec<Definition> {
+    fn name(&self) -> &str {
+        "misc"
+    }
+
+    fn load(&self, symbols: &Interner) -> Vec<Definition> {
         vec![
-            Definition::new(print_sig(), handler!(print_handler)),
-            Definition::new(return_sig(), handler!(return_handler)),
+            Definition::new(sig!(symbols, (.print Any)), handler!(print_handler)),
+            Definition::new(sig!(symbols, (.return Any)), handler!(return_handler)),
+            Definition::new(sig!(symbols, (.eval Form)), handler!(eval_handler)),
         ]
     }
 }
 
-fn print_sig() -> Token {
-    Token::Lst(vec![
-        Token::Keyword(".print".to_owned()),
-        Token::Var("Any".to_owned()),
-    ])
-}
-
-fn return_sig() -> Token {
-    Token::Lst(vec![
-        Token::Keyword(".return".to_owned()),
-        Token::Var("Any".to_owned()),
-    ])
-}
-
-fn print_handler(_: &mut EvalState, token: Token) -> EvalResult {
+fn print_handler(state: &mut EvalState, token: Token) -> EvalResult {
     if let Token::Lst(lst) = token {
         if let [_, t] = &*lst.clone() {
-            print_token(t.clone());
+            print_token(&state.symbols, t.clone());
             return Ok(Token::Bool(true));
         }
     }
@@ -47,3 +38,15 @@ fn return_handler(state: &mut EvalState, token: Token) -> EvalResult {
 
     Ok(Token::Bool(false))
 }
+
+// (.eval '(likes X tea)) is (likes X tea): the argument is data once evaluated,
+// and evaluated once more as the result
+fn eval_handler(_: &mut EvalState, token: Token) -> EvalResult {
+    if let Token::Lst(lst) = token {
+        if let [_, form] = &*lst {
+            return Ok(form.clone());
+        }
+    }
+
+    Ok(Token::Bool(false))
+}
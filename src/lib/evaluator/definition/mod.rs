@@ -1,24 +1,149 @@
 pub mod def;
+pub mod exception;
 pub mod math;
 pub mod misc;
+pub mod native;
 mod prelude;
+pub mod store;
+pub mod stream;
+pub mod table;
 
-use crate::lib::evaluator::{EvalResult, EvalState};
-use crate::lib::token::Token;
+use crate::error::EvalError;
+use crate::evaluator::vm::Clause;
+use crate::evaluator::{EvalResult, EvalState};
+use crate::symbol::Interner;
+use crate::token::Token;
+use native::NativeFunction;
 
 use std::sync::Arc;
 
-type Handler = Arc<Box<dyn Fn(&mut EvalState, Token) -> EvalResult + Send + Sync>>;
+/// The native code run for a call matching a [`Definition`]. It is given the
+/// call, its arguments evaluated unless the definition is special, and returns
+/// what the call is rewritten to. [`Token::Unknown`] leaves the call as it is.
+pub type Handler = Arc<Box<dyn Fn(&mut EvalState, Token) -> EvalResult + Send + Sync>>;
 
+/// A signature, with variables and wildcards standing for anything, and the
+/// handler run for calls of its shape.
 #[derive(Clone)]
 pub struct Definition {
     pub signature: Token,
     handler: Handler,
+    special: bool,
+    // registered rust functions, whose calls are checked as built-ins' are
+    native: bool,
+    // the body of a clause written with .def or .dec, whose head is the
+    // signature
+    body: Option<Token>,
+    clause: Option<Arc<Clause>>,
+    // the name of the module it was loaded with
+    module: Option<Arc<str>>,
 }
 
 impl Definition {
     pub fn new(signature: Token, handler: Handler) -> Self {
-        Definition { signature, handler }
+        Definition {
+            signature,
+            handler,
+            special: false,
+            native: false,
+            body: None,
+            clause: None,
+            module: None,
+        }
+    }
+
+    /// A definition receiving its arguments as written instead of evaluated,
+    /// like `.def` which must keep its body for later.
+    pub fn special(signature: Token, handler: Handler) -> Self {
+        Definition {
+            signature,
+            handler,
+            special: true,
+            native: false,
+            body: None,
+            clause: None,
+            module: None,
+        }
+    }
+
+    /// A definition calling a Rust function with the arguments of the calls
+    /// matching `signature`, converted as for
+    /// [`Engine::register`](crate::Engine::register). A call with the wrong
+    /// number of arguments is an error, as it is for keywords and operators.
+    pub fn function<Args, F: NativeFunction<Args>>(signature: Token, f: F) -> Self {
+        Definition {
+            native: true,
+            ..Definition::new(signature, native::handler(f))
+        }
+    }
+
+    // a clause written in the language. given a compiled clause, the
+    // evaluator runs it through the vm rather than matching the signature and
+    // calling handler, which must give the same result
+    pub(crate) fn rule(head: Token, body: Token, handler: Handler, clause: Option<Clause>) -> Self {
+        Definition {
+            signature: head,
+            handler,
+            special: false,
+            native: false,
+            body: Some(body),
+            clause: clause.map(Arc::new),
+            module: None,
+        }
+    }
+
+    pub fn is_special(&self) -> bool {
+        self.special
+    }
+
+    pub fn is_native(&self) -> bool {
+        self.native
+    }
+
+    /// The name of the module that gave the definition, if one did.
+    pub fn module(&self) -> Option<&str> {
+        self.module.as_deref()
+    }
+
+    // the definition as given by module, along with the same definition
+    // under the name qualified by the module's, e.g. (.misc/print Any) for
+    // (.print Any). operators have no qualified name
+    pub(crate) fn loaded_by(self, symbols: &Interner, module: &Arc<str>) -> Vec<Definition> {
+        let definition = Definition {
+            module: Some(module.clone()),
+            ..self
+        };
+
+        let qualified = match &definition.signature {
+            Token::Lst(lst) => lst
+                .first()
+                .and_then(|head| qualify(symbols, module, head))
+                .map(|head| {
+                    let mut signature = lst.to_vec();
+                    signature[0] = head;
+                    Token::list(signature)
+                }),
+            _ => None,
+        };
+
+        match qualified {
+            Some(signature) => {
+                let alias = Definition {
+                    signature,
+                    ..definition.clone()
+                };
+                vec![definition, alias]
+            }
+            None => vec![definition],
+        }
+    }
+
+    pub fn body(&self) -> Option<&Token> {
+        self.body.as_ref()
+    }
+
+    pub(crate) fn clause(&self) -> Option<&Clause> {
+        self.clause.as_deref()
     }
 
     pub fn run(&self, state: &mut EvalState, token: Token) -> EvalResult {
@@ -26,13 +151,45 @@ impl Definition {
     }
 }
 
-pub trait Module {
-    fn load() -> Vec<Definition>;
+fn qualify(symbols: &Interner, module: &str, head: &Token) -> Option<Token> {
+    match head {
+        Token::Str(name) => Some(symbols.str(&format!("{}/{}", module, symbols.resolve(*name)))),
+        Token::Keyword(name) => {
+            let name = symbols.resolve(*name);
+            Some(symbols.keyword(&format!(".{}/{}", module, &name[1..])))
+        }
+        _ => None,
+    }
+}
+
+/// A set of definitions loaded into an engine together, tried in the order
+/// they are given. A module can hold state of its own, such as settings or a
+/// connection, which its handlers share by capturing it.
+pub trait Module: Send + Sync + 'static {
+    /// The name its definitions can also be called by, e.g. `(.misc/print X)`
+    /// for `(.print X)`, and which it is unloaded by.
+    fn name(&self) -> &str;
+
+    /// The names of the modules that must be loaded before this one.
+    fn dependencies(&self) -> &[&str] {
+        &[]
+    }
+
+    fn load(&self, symbols: &Interner) -> Vec<Definition>;
+
+    /// Run once the definitions are added. An error unloads them again.
+    fn on_load(&self, _state: &mut EvalState) -> Result<(), EvalError> {
+        Ok(())
+    }
+
+    /// Run before the definitions are removed.
+    fn on_unload(&self, _state: &mut EvalState) {}
 }
 
+/// Makes a [`Handler`](crate::Handler) of a function or closure.
 #[macro_export]
 macro_rules! handler {
     ($f:expr) => {
-        Arc::new(Box::new($f))
+        ::std::sync::Arc::new(::std::boxed::Box::new($f))
     };
 }
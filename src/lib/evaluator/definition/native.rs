@@ -0,0 +1,304 @@
+use crate::evaluator::definition::prelude::*;
+use crate::evaluator::definition::Handler;
+
+/// A Rust value an argument of a registered function can be converted to.
+pub trait FromToken: Sized {
+    /// What the argument is called when it has the wrong type, e.g. `number`.
+    const EXPECTED: &'static str;
+
+    fn from_token(token: &Token, symbols: &Interner) -> Option<Self>;
+}
+
+/// A Rust value a registered function can return.
+pub trait IntoToken {
+    fn into_token(self, symbols: &Interner) -> Token;
+}
+
+/// What a registered function returns: a value, or a value or an error.
+pub trait IntoEvalResult {
+    fn into_eval_result(self, symbols: &Interner) -> EvalResult;
+}
+
+impl<T: IntoToken> IntoEvalResult for T {
+    fn into_eval_result(self, symbols: &Interner) -> EvalResult {
+        Ok(self.into_token(symbols))
+    }
+}
+
+impl<T: IntoToken> IntoEvalResult for Result<T, EvalError> {
+    fn into_eval_result(self, symbols: &Interner) -> EvalResult {
+        self.map(|value| value.into_token(symbols))
+    }
+}
+
+impl FromToken for Token {
+    const EXPECTED: &'static str = "anything";
+
+    fn from_token(token: &Token, _: &Interner) -> Option<Self> {
+        Some(token.clone())
+    }
+}
+
+impl FromToken for f32 {
+    const EXPECTED: &'static str = "number";
+
+    fn from_token(token: &Token, _: &Interner) -> Option<Self> {
+        match token {
+            Token::Number(num) => Some(*num),
+            _ => None,
+        }
+    }
+}
+
+impl FromToken for f64 {
+    const EXPECTED: &'static str = "number";
+
+    fn from_token(token: &Token, symbols: &Interner) -> Option<Self> {
+        f32::from_token(token, symbols).map(f64::from)
+    }
+}
+
+impl FromToken for i64 {
+    const EXPECTED: &'static str = "integer";
+
+    fn from_token(token: &Token, symbols: &Interner) -> Option<Self> {
+        f32::from_token(token, symbols)
+            .filter(|num| num.fract() == 0.0)
+            .map(|num| num as i64)
+    }
+}
+
+impl FromToken for usize {
+    const EXPECTED: &'static str = "natural number";
+
+    fn from_token(token: &Token, symbols: &Interner) -> Option<Self> {
+        i64::from_token(token, symbols).and_then(|num| usize::try_from(num).ok())
+    }
+}
+
+impl FromToken for bool {
+    const EXPECTED: &'static str = "boolean";
+
+    fn from_token(token: &Token, _: &Interner) -> Option<Self> {
+        match token {
+            Token::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+}
+
+impl FromToken for String {
+    const EXPECTED: &'static str = "string";
+
+    fn from_token(token: &Token, symbols: &Interner) -> Option<Self> {
+        match token {
+            Token::Str(s) => Some(symbols.resolve(*s).to_string()),
+            _ => None,
+        }
+    }
+}
+
+impl<T: FromToken> FromToken for Vec<T> {
+    const EXPECTED: &'static str = "list";
+
+    fn from_token(token: &Token, symbols: &Interner) -> Option<Self> {
+        match token {
+            Token::Lst(lst) => lst.iter().map(|t| T::from_token(t, symbols)).collect(),
+            _ => None,
+        }
+    }
+}
+
+impl IntoToken for Token {
+    fn into_token(self, _: &Interner) -> Token {
+        self
+    }
+}
+
+impl IntoToken for f32 {
+    fn into_token(self, _: &Interner) -> Token {
+        Token::Number(self)
+    }
+}
+
+impl IntoToken for f64 {
+    fn into_token(self, _: &Interner) -> Token {
+        Token::Number(self as f32)
+    }
+}
+
+impl IntoToken for i64 {
+    fn into_token(self, _: &Interner) -> Token {
+        Token::Number(self as f32)
+    }
+}
+
+impl IntoToken for usize {
+    fn into_token(self, _: &Interner) -> Token {
+        Token::Number(self as f32)
+    }
+}
+
+impl IntoToken for bool {
+    fn into_token(self, _: &Interner) -> Token {
+        Token::Bool(self)
+    }
+}
+
+impl IntoToken for String {
+    fn into_token(self, symbols: &Interner) -> Token {
+        symbols.str(&self)
+    }
+}
+
+impl IntoToken for &str {
+    fn into_token(self, symbols: &Interner) -> Token {
+        symbols.str(self)
+    }
+}
+
+// functions run for their effect are true, as .set is
+impl IntoToken for () {
+    fn into_token(self, _: &Interner) -> Token {
+        Token::Bool(true)
+    }
+}
+
+impl<T: IntoToken> IntoToken for Vec<T> {
+    fn into_token(self, symbols: &Interner) -> Token {
+        Token::list(self.into_iter().map(|t| t.into_token(symbols)).collect())
+    }
+}
+
+/// A Rust function or closure that can be registered, taking arguments that
+/// implement [`FromToken`] and returning something that implements
+/// [`IntoEvalResult`]. `Args` is the tuple of its argument types.
+pub trait NativeFunction<Args>: Send + Sync + 'static {
+    fn arity(&self) -> usize;
+
+    // the result of a call to head, Token::Unknown leaving the call as it is
+    fn call(&self, symbols: &Interner, head: &Token, args: &[Token]) -> EvalResult;
+}
+
+// the argument converted, None while it is unbound so the call can still be
+// used inside a definition, as arithmetic can
+fn argument<T: FromToken>(
+    symbols: &Interner,
+    head: &Token,
+    arg: &Token,
+) -> Result<Option<T>, EvalError> {
+    match T::from_token(arg, symbols) {
+        Some(value) => Ok(Some(value)),
+        None if matches!(arg, Token::Var(_) | Token::Wildcard(_)) => Ok(None),
+        None => Err(EvalError::TypeMismatch {
+            definition: render_token(symbols, head),
+            expected: T::EXPECTED.to_owned(),
+            found: render_token(symbols, arg),
+        }),
+    }
+}
+
+macro_rules! native_function {
+    ($arity:expr $(, $ty:ident $arg:ident)*) => {
+        impl<F, R $(, $ty)*> NativeFunction<($($ty,)*)> for F
+        where
+            F: Fn($($ty),*) -> R + Send + Sync + 'static,
+            R: IntoEvalResult,
+            $($ty: FromToken,)*
+        {
+            fn arity(&self) -> usize {
+                $arity
+            }
+
+            #[allow(unused_variables, unused_mut)]
+            fn call(&self, symbols: &Interner, head: &Token, args: &[Token]) -> EvalResult {
+                let mut args = args.iter();
+                $(
+                    let $arg = match args.next() {
+                        Some(arg) => argument::<$ty>(symbols, head, arg)?,
+                        None => return Ok(Token::Unknown),
+                    };
+                )*
+                $(
+                    let $arg = match $arg {
+                        Some(value) => value,
+                        None => return Ok(Token::Unknown),
+                    };
+                )*
+                self($($arg),*).into_eval_result(symbols)
+            }
+        }
+    };
+}
+
+native_function!(0);
+native_function!(1, A a);
+native_function!(2, A a, B b);
+native_function!(3, A a, B b, C c);
+native_function!(4, A a, B b, C c, D d);
+native_function!(5, A a, B b, C c, D d, E e);
+native_function!(6, A a, B b, C c, D d, E e, G g);
+
+// the handler calling f with the arguments of a call
+pub(crate) fn handler<Args, F: NativeFunction<Args>>(f: F) -> Handler {
+    handler!(move |state: &mut EvalState, token: Token| match token {
+        Token::Lst(lst) => f.call(&state.symbols, &lst[0], &lst[1..]),
+        _ => Ok(Token::Unknown),
+    })
+}
+
+// a definition of (head Arg1 Arg2 ...) calling f
+pub fn definition<Args, F: NativeFunction<Args>>(
+    symbols: &Interner,
+    head: Token,
+    f: F,
+) -> Definition {
+    let mut signature = vec![head];
+    for i in 0..f.arity() {
+        signature.push(symbols.var(&format!("Arg{}", i + 1)));
+    }
+
+    Definition::function(Token::list(signature), f)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+    use crate::error::EvalError;
+    use crate::testing::eval;
+    use crate::token::Token;
+
+    #[test]
+    fn registered_functions_convert_and_check_their_arguments() {
+        let mut engine = Engine::new();
+        engine.register("concat", |a: String, b: String| a + &b);
+        engine.register(".half", |n: i64| -> Result<f32, EvalError> {
+            match n % 2 {
+                0 => Ok(n as f32 / 2.0),
+                _ => Err(EvalError::Thrown(Token::Bool(false), "false".to_owned())),
+            }
+        });
+        engine.register("sum", |nums: Vec<f32>| nums.iter().sum::<f32>());
+
+        assert_eq!(
+            eval(&mut engine, "(concat tea pot) (.half 8) (sum (1 2 3))"),
+            "(teapot 4 6)"
+        );
+        assert_eq!(
+            eval(&mut engine, "(.def (greet X) (concat hello X)) (greet bob)"),
+            "(true hellobob)"
+        );
+        assert_eq!(
+            eval(&mut engine, "(.def (f X) (concat X tea)) (f Y)"),
+            "(true (concat Y tea))"
+        );
+
+        assert!(
+            eval(&mut engine, "(concat tea)").starts_with("concat expects 2 argument(s), found 1")
+        );
+        assert!(eval(&mut engine, "(concat tea 1)")
+            .starts_with("type mismatch in concat: expected string, found 1"));
+        assert!(eval(&mut engine, "(.half 1.5)").starts_with("type mismatch"));
+        assert!(eval(&mut engine, "(.half 3)").starts_with("uncaught exception false"));
+    }
+}
@@ -1,5 +1,7 @@
-pub use crate::handler;
-pub use crate::lib::evaluator::definition::{Definition, Module};
-pub use crate::lib::evaluator::{EvalResult, EvalState};
-pub use crate::lib::token::Token;
-pub use std::sync::Arc;
+pub use crate::error::EvalError;
+pub use crate::evaluator::definition::{Definition, Module};
+pub use crate::evaluator::{EvalResult, EvalState};
+pub use crate::printer::render_token;
+pub use crate::symbol::{sym, Interner, Symbol};
+pub use crate::token::Token;
+pub use crate::{handler, sig};
@@ -0,0 +1,42 @@
+use crate::evaluator::definition::prelude::*;
+
+pub struct Store;
+
+impl Module for Store {
+    fn name(&self) -> &str {
+        "store"
+    }
+
+    fn load(&self, symbols: &Interner) -> Vec<Definition> {
+        vec![
+            Definition::new(sig!(symbols, (.set Key Value)), handler!(set_handler)),
+            Definition::new(sig!(symbols, (.get Key)), handler!(get_handler)),
+        ]
+    }
+}
+
+// (.set counter 1) stores 1 under counter, replacing what was there
+fn set_handler(state: &mut EvalState, token: Token) -> EvalResult {
+    if let Token::Lst(lst) = token {
+        if let [_, key, value] = &*lst {
+            state.values.insert(key.clone(), value.clone());
+            // tabled answers may have been computed from the old value
+            state.table.invalidate();
+            return Ok(Token::Bool(true));
+        }
+    }
+
+    Ok(Token::Bool(false))
+}
+
+// (.get counter) is the value stored under counter, and is left as it is
+// while nothing is
+fn get_handler(state: &mut EvalState, token: Token) -> EvalResult {
+    if let Token::Lst(lst) = token {
+        if let [_, key] = &*lst {
+            return Ok(state.values.get(key).cloned().unwrap_or(Token::Unknown));
+        }
+    }
+
+    Ok(Token::Bool(false))
+}
@@ -0,0 +1,244 @@
+use crate::evaluator::definition::prelude::*;
+
+use crate::evaluator::definition::def::rule;
+
+// a stream is (.stream-cons H T), whose tail T is only evaluated once read,
+// or () where it ends. (.take N S) and (.drop N S) are lazy calls, so
+// (.take 0 T) leaves the T it is given alone, and otherwise read a cell at a
+// time, calling themselves again in tail position on the tail, so a long
+// stream takes no deeper a stack than a short one
+pub struct Streams;
+
+fn lazy_patterns(symbols: &Interner) -> [Token; 2] {
+    [sig!(symbols, (.take N S)), sig!(symbols, (.drop N S))]
+}
+
+impl Module for Streams {
+    fn name(&self) -> &str {
+        "streams"
+    }
+
+    fn load(&self, symbols: &Interner) -> Vec<Definition> {
+        vec![
+            // the first N elements, as a list. (.take N S Taken) carries
+            // those taken so far
+            rule(&sig!(symbols, (.take 0 S)), &sig!(symbols, ()), false),
+            Definition::new(sig!(symbols, (.take N S)), handler!(take_handler)),
+            Definition::new(sig!(symbols, (.take N S Taken)), handler!(take_handler)),
+            // the stream after its first N elements
+            rule(&sig!(symbols, (.drop 0 S)), &sig!(symbols, S), false),
+            Definition::new(sig!(symbols, (.drop N S)), handler!(drop_handler)),
+            // X, (F X), (F (F X)), ...
+            rule(
+                &sig!(symbols, (.iterate F X)),
+                &sig!(symbols, (.stream-cons X (.iterate F (F X)))),
+                false,
+            ),
+            Definition::new(sig!(symbols, (.range From)), handler!(range_handler)),
+            Definition::new(sig!(symbols, (.range From To)), handler!(range_handler)),
+        ]
+    }
+
+    fn on_load(&self, state: &mut EvalState) -> Result<(), EvalError> {
+        let patterns = lazy_patterns(&state.symbols);
+        state.lazy_patterns.extend(patterns);
+        Ok(())
+    }
+
+    fn on_unload(&self, state: &mut EvalState) {
+        let patterns = lazy_patterns(&state.symbols);
+        state
+            .lazy_patterns
+            .retain(|pattern| !patterns.contains(pattern));
+    }
+}
+
+// the count and cell of (.take N S) or (.drop N S), whose stream has its
+// first cell forced. None when the stream isn't one, or has ended
+fn count_and_cell<'a>(
+    state: &EvalState,
+    name: &str,
+    count: &Token,
+    stream: &'a Token,
+) -> Result<Option<(f32, &'a Token, &'a Token)>, EvalError> {
+    let count = match count {
+        Token::Number(n) => *n,
+        Token::Var(_) | Token::Wildcard(_) | Token::Lst(_) => return Ok(None),
+        found => {
+            return Err(EvalError::TypeMismatch {
+                definition: name.to_owned(),
+                expected: "number".to_owned(),
+                found: render_token(&state.symbols, found),
+            })
+        }
+    };
+
+    match stream {
+        Token::Lst(lst) => match &**lst {
+            [Token::Keyword(sym::STREAM_CONS), head, tail] => Ok(Some((count, head, tail))),
+            _ => Ok(None),
+        },
+        _ => Ok(None),
+    }
+}
+
+fn quote(token: Token) -> Token {
+    Token::list(vec![Token::Keyword(sym::QUOTE), token])
+}
+
+fn take_handler(state: &mut EvalState, token: Token) -> EvalResult {
+    let (count, stream, taken) = match &token {
+        Token::Lst(lst) => match &**lst {
+            [_, count, stream] => (count, stream, &[][..]),
+            [_, count, stream, Token::Lst(taken)] => (count, stream, &taken[..]),
+            _ => return Ok(Token::Unknown),
+        },
+        _ => return Ok(Token::Unknown),
+    };
+
+    let mut taken = taken.to_vec();
+    let (count, head, tail) = match count_and_cell(state, ".take", count, stream)? {
+        Some(cell) => cell,
+        None if *stream == Token::list(vec![]) => return Ok(quote(Token::list(taken))),
+        None => return Ok(Token::Unknown),
+    };
+    if count < 1.0 {
+        return Ok(quote(Token::list(taken)));
+    }
+
+    // heads that are lists are kept quoted in their cells
+    taken.push(match head {
+        Token::Lst(lst) if lst.len() == 2 && lst[0] == Token::Keyword(sym::QUOTE) => lst[1].clone(),
+        head => head.clone(),
+    });
+    if count < 2.0 {
+        return Ok(quote(Token::list(taken)));
+    }
+
+    Ok(Token::list(vec![
+        state.symbols.keyword(".take"),
+        Token::Number(count - 1.0),
+        tail.clone(),
+        quote(Token::list(taken)),
+    ]))
+}
+
+fn drop_handler(state: &mut EvalState, token: Token) -> EvalResult {
+    let (count, stream) = match &token {
+        Token::Lst(lst) => match &**lst {
+            [_, count, stream] => (count, stream),
+            _ => return Ok(Token::Unknown),
+        },
+        _ => return Ok(Token::Unknown),
+    };
+
+    let (count, tail) = match count_and_cell(state, ".drop", count, stream)? {
+        Some((count, _, tail)) => (count, tail),
+        None if *stream == Token::list(vec![]) => return Ok(Token::list(vec![])),
+        None => return Ok(Token::Unknown),
+    };
+
+    if count < 1.0 {
+        Ok(stream.clone())
+    } else if count < 2.0 {
+        Ok(tail.clone())
+    } else {
+        Ok(Token::list(vec![
+            state.symbols.keyword(".drop"),
+            Token::Number(count - 1.0),
+            tail.clone(),
+        ]))
+    }
+}
+
+// (.range 1) is 1, 2, 3, ... and (.range 1 4) is 1, 2, 3
+fn range_handler(state: &mut EvalState, token: Token) -> EvalResult {
+    let lst = match &token {
+        Token::Lst(lst) => lst,
+        _ => return Ok(Token::Unknown),
+    };
+
+    let mut bounds = vec![];
+    for bound in &lst[1..] {
+        match bound {
+            Token::Number(n) => bounds.push(*n),
+            Token::Var(_) | Token::Wildcard(_) | Token::Lst(_) => return Ok(Token::Unknown),
+            found => {
+                return Err(EvalError::TypeMismatch {
+                    definition: ".range".to_owned(),
+                    expected: "number".to_owned(),
+                    found: render_token(&state.symbols, found),
+                })
+            }
+        }
+    }
+
+    let (from, rest) = match &bounds[..] {
+        [from] => (
+            *from,
+            vec![state.symbols.keyword(".range"), Token::Number(from + 1.0)],
+        ),
+        [from, to] if from < to => (
+            *from,
+            vec![
+                state.symbols.keyword(".range"),
+                Token::Number(from + 1.0),
+                Token::Number(*to),
+            ],
+        ),
+        _ => return Ok(Token::list(vec![])),
+    };
+
+    Ok(Token::list(vec![
+        Token::Keyword(sym::STREAM_CONS),
+        Token::Number(from),
+        Token::list(rest),
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+    use crate::testing::eval;
+
+    #[test]
+    fn streams_force_only_what_is_read() {
+        let mut engine = Engine::new();
+        eval(
+            &mut engine,
+            "(.def (double X) (* X 2))
+             (.def (ones) (.stream-cons 1 (ones)))",
+        );
+
+        assert_eq!(
+            eval(
+                &mut engine,
+                "(.take 3 (ones)) (.take 4 (.iterate double 1))"
+            ),
+            "((1 1 1) (1 2 4 8))"
+        );
+        assert_eq!(
+            eval(
+                &mut engine,
+                "(.take 5 (.range 1 4)) (.take 2 (.drop 3 (.range 0)))"
+            ),
+            "((1 2 3) (3 4))"
+        );
+        // reading a long stream takes no deeper a stack than a short one
+        let taken = (0..2000).map(|n| n.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            eval(&mut engine, "(.take 2000 (.range 0))"),
+            format!("(({}))", taken.join(" "))
+        );
+        assert_eq!(
+            eval(&mut engine, "(.take 2 (.drop 5000 (.range 0)))"),
+            "((5000 5001))"
+        );
+        assert_eq!(eval(&mut engine, "(.take 0 (.throw boom))"), "(())");
+        // a stored stream prints as far as it has been read
+        eval(&mut engine, "(.set s (.range 10))");
+        assert_eq!(eval(&mut engine, "(.get s)"), "((10 ...))");
+        eval(&mut engine, "(.take 3 (.get s))");
+        assert_eq!(eval(&mut engine, "(.get s)"), "((10 11 12 ...))");
+    }
+}
@@ -0,0 +1,63 @@
+use crate::database::Change;
+use crate::evaluator::definition::prelude::*;
+use crate::evaluator::match_token;
+
+pub struct Tabling;
+
+impl Module for Tabling {
+    fn name(&self) -> &str {
+        "tabling"
+    }
+
+    fn load(&self, symbols: &Interner) -> Vec<Definition> {
+        vec![
+            Definition::special(sig!(symbols, (.table Pattern)), handler!(table_handler)),
+            Definition::special(
+                sig!(symbols, (.table-answers Pattern)),
+                handler!(answers_handler),
+            ),
+            Definition::special(sig!(symbols, (.table-clear)), handler!(clear_handler)),
+        ]
+    }
+}
+
+// (.table (fib N)) caches the answers of every ground call matching (fib N)
+fn table_handler(state: &mut EvalState, token: Token) -> EvalResult {
+    if let Token::Lst(lst) = token {
+        if let [_, pattern] = &*lst {
+            state.table.patterns.push(pattern.clone());
+            state.record(Change::Table(pattern.clone()));
+            return Ok(Token::Bool(true));
+        }
+    }
+
+    Ok(Token::Bool(false))
+}
+
+// (.table-answers (fib N)) lists the cached (call answer) pairs matching
+// (fib N), in no particular order
+fn answers_handler(state: &mut EvalState, token: Token) -> EvalResult {
+    if let Token::Lst(lst) = token {
+        if let [_, pattern] = &*lst {
+            let answers = state
+                .table
+                .complete_answers()
+                .into_iter()
+                .filter(|(call, _)| match_token(pattern, call))
+                .map(|(call, answer)| Token::list(vec![call, answer]))
+                .collect();
+            // quoted, as the calls would otherwise be made again
+            return Ok(Token::list(vec![
+                Token::Keyword(sym::QUOTE),
+                Token::Lst(answers),
+            ]));
+        }
+    }
+
+    Ok(Token::Bool(false))
+}
+
+fn clear_handler(state: &mut EvalState, _: Token) -> EvalResult {
+    state.table.invalidate();
+    Ok(Token::Bool(true))
+}
@@ -0,0 +1,388 @@
+use crate::database::Change;
+use crate::error::EvalError;
+use crate::evaluator::{Evaluator, Step};
+use crate::parser::Parser;
+use crate::printer::render_token;
+use crate::symbol::sym;
+use crate::token::Token;
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// (.import family) loads family.lx, looked for next to the file importing it
+// and then in each directory of the search path. (.import "lib/family.lx")
+// names the file itself.
+//
+// a file is a module named after it and is loaded once. the names it defines
+// are qualified by the module's, so (size X) in family.lx is (family/size X)
+// both inside and outside of it, and two files can each define size. the
+// names listed after :only can be called unqualified by the importing file.
+//
+// names defined with .defp, or left out of (.export (name ...)) when a file
+// has one, are private: they are qualified by a hidden symbol no source can
+// name, so only the file itself calls them
+impl Evaluator {
+    pub(super) fn eval_import(&mut self, lst: &[Token]) -> Result<Step, EvalError> {
+        let symbols = self.curr_state.symbols.clone();
+        let (target, only) = match lst {
+            [_, Token::Str(target)] => (*target, &[][..]),
+            [_, Token::Str(target), Token::Keyword(sym::ONLY), Token::Lst(names)] => {
+                (*target, &names[..])
+            }
+            _ => {
+                return Err(EvalError::TypeMismatch {
+                    definition: ".import".to_owned(),
+                    expected: "(.import module) or (.import module :only (name ...))".to_owned(),
+                    found: render_token(&symbols, &Token::list(lst.to_vec())),
+                })
+            }
+        };
+
+        let target = symbols.resolve(target);
+        // the dependencies of a package are imported by name from anywhere
+        let (path, module): (PathBuf, Arc<str>) = match self.curr_state.packages.get(&*target) {
+            Some(entry) => (entry.clone(), target.clone()),
+            None => {
+                let path = self.find_module(&target)?;
+                let module = match path.file_stem() {
+                    Some(stem) => Arc::from(stem.to_string_lossy().as_ref()),
+                    None => target.clone(),
+                };
+                (path, module)
+            }
+        };
+
+        if !self.curr_state.imports.contains_key(&path) {
+            if let Some(at) = self.curr_state.files.iter().position(|file| *file == path) {
+                let mut cycle: Vec<String> = self.curr_state.files[at..]
+                    .iter()
+                    .map(|file| file.display().to_string())
+                    .collect();
+                cycle.push(path.display().to_string());
+                return Err(EvalError::ImportCycle { cycle });
+            }
+            if self
+                .curr_state
+                .imports
+                .values()
+                .any(|other| *other == module)
+            {
+                return Err(EvalError::ImportFailed {
+                    module: module.to_string(),
+                    reason: "another module of that name is loaded".to_owned(),
+                });
+            }
+
+            self.load_file(&path, &module)?;
+            self.curr_state.imports.insert(path, module.clone());
+        }
+
+        for name in only {
+            self.import_name(&module, name)?;
+        }
+
+        Ok(Step::Done(Token::Bool(true)))
+    }
+
+    // the file of target, made absolute so each file is loaded once however
+    // it is reached
+    fn find_module(&self, target: &str) -> Result<PathBuf, EvalError> {
+        let file = match target.ends_with(".lx") {
+            true => PathBuf::from(target),
+            false => PathBuf::from(format!("{}.lx", target)),
+        };
+
+        let base = match self.curr_state.files.last().and_then(|file| file.parent()) {
+            Some(dir) => dir.to_owned(),
+            None => PathBuf::from("."),
+        };
+        let dirs: Vec<&Path> = std::iter::once(base.as_path())
+            .chain(self.curr_state.search_path.iter().map(|dir| dir.as_path()))
+            .collect();
+
+        for dir in &dirs {
+            let candidate = dir.join(&file);
+            if candidate.is_file() {
+                return Ok(candidate.canonicalize().unwrap_or(candidate));
+            }
+        }
+
+        Err(EvalError::ModuleNotFound {
+            module: target.to_owned(),
+            searched: dirs.iter().map(|dir| dir.display().to_string()).collect(),
+        })
+    }
+
+    fn load_file(&mut self, path: &Path, module: &str) -> Result<(), EvalError> {
+        let failed = |reason: String| EvalError::ImportFailed {
+            module: module.to_owned(),
+            reason,
+        };
+
+        let source = std::fs::read_to_string(path).map_err(|err| failed(err.to_string()))?;
+        let parser = Parser::new(self.curr_state.symbols.clone());
+        let (forms, _) = parser
+            .parse_with_spans(format!("({})", source))
+            .map_err(|err| failed(err.to_string()))?;
+
+        let forms = match forms {
+            Token::Lst(forms) => forms,
+            _ => return Ok(()),
+        };
+        let names = self.qualified_names(module, &forms);
+        let forms: Vec<Token> = forms.iter().map(|form| qualify(&names, form)).collect();
+
+        self.curr_state.files.push(path.to_owned());
+        let res = forms
+            .into_iter()
+            .try_for_each(|form| self.eval_token(form).map(|_| ()));
+        self.curr_state.files.pop();
+        res
+    }
+
+    // copies the clauses of module/name as clauses of name
+    fn import_name(&mut self, module: &str, name: &Token) -> Result<(), EvalError> {
+        let symbols = self.curr_state.symbols.clone();
+        let unqualified = match name {
+            Token::Str(name) => *name,
+            _ => {
+                return Err(EvalError::TypeMismatch {
+                    definition: ".import".to_owned(),
+                    expected: "name".to_owned(),
+                    found: render_token(&symbols, name),
+                })
+            }
+        };
+        // a name stands for the clauses of a single module
+        match self.curr_state.imported_names.get(&unqualified) {
+            Some(other) if &**other != module => {
+                return Err(EvalError::ImportFailed {
+                    module: module.to_owned(),
+                    reason: format!(
+                        "{} is already imported from {}",
+                        render_token(&symbols, name),
+                        other
+                    ),
+                })
+            }
+            _ => {}
+        }
+
+        let qualified = symbols.str(&format!("{}/{}", module, symbols.resolve(unqualified)));
+        if self.curr_state.private.contains(&qualified) {
+            return Err(EvalError::PrivateDefinition {
+                definition: render_token(&symbols, &qualified),
+                module: module.to_owned(),
+            });
+        }
+
+        let clauses: Vec<(Token, Token)> = self
+            .clauses()
+            .into_iter()
+            .filter(|(head, _)| matches!(head, Token::Lst(lst) if lst.first() == Some(&qualified)))
+            .collect();
+        if clauses.is_empty() {
+            return Err(EvalError::ImportFailed {
+                module: module.to_owned(),
+                reason: format!("it doesn't define {}", render_token(&symbols, name)),
+            });
+        }
+
+        let existing: HashSet<(Token, Token)> = self.clauses().into_iter().collect();
+        for (head, body) in clauses {
+            let mut items = match head {
+                Token::Lst(lst) => lst.to_vec(),
+                _ => continue,
+            };
+            items[0] = Token::Str(unqualified);
+            let head = Token::list(items);
+            // importing the same name again adds nothing
+            if !existing.contains(&(head.clone(), body.clone())) {
+                self.add_clause(&head, &body);
+            }
+        }
+        self.curr_state
+            .imported_names
+            .insert(unqualified, Arc::from(module));
+
+        Ok(())
+    }
+
+    // the names given clauses by the top-level .def, .defp and .dec forms of
+    // a file, each with the name it is called by once qualified
+    fn qualified_names(&mut self, module: &str, forms: &[Token]) -> HashMap<Token, Token> {
+        let symbols = self.curr_state.symbols.clone();
+        let defines = [symbols.keyword(".def"), symbols.keyword(".dec")];
+        let private = symbols.keyword(".defp");
+        let export = symbols.keyword(".export");
+
+        let mut exports: Option<HashSet<Token>> = None;
+        let mut names: HashMap<Token, bool> = HashMap::new();
+        for form in forms {
+            match form {
+                Token::Lst(form) if form.len() == 2 && form[0] == export => {
+                    if let Token::Lst(listed) = &form[1] {
+                        exports
+                            .get_or_insert_with(HashSet::new)
+                            .extend(listed.iter().cloned());
+                    }
+                }
+                Token::Lst(form) if form.len() >= 2 => {
+                    let is_private = form[0] == private;
+                    if !is_private && !defines.contains(&form[0]) {
+                        continue;
+                    }
+                    if let Token::Lst(head) = &form[1] {
+                        if let Some(name @ Token::Str(_)) = head.first() {
+                            *names.entry(name.clone()).or_default() |= is_private;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        names
+            .into_iter()
+            .map(|(name, is_private)| {
+                let text = format!("{}/{}", module, render_token(&symbols, &name));
+                let is_private = is_private
+                    || exports
+                        .as_ref()
+                        .is_some_and(|exports| !exports.contains(&name));
+                let qualified = match is_private {
+                    true => {
+                        let name = symbols.str(&text);
+                        self.curr_state.private.insert(name.clone());
+                        self.curr_state.record(Change::Private(name));
+                        Token::Str(symbols.hidden(&text))
+                    }
+                    false => symbols.str(&text),
+                };
+                (name, qualified)
+            })
+            .collect()
+    }
+}
+
+// token with every call to one of names made a call to its qualified name
+fn qualify(names: &HashMap<Token, Token>, token: &Token) -> Token {
+    match token {
+        Token::Lst(lst) => Token::list(
+            lst.iter()
+                .enumerate()
+                .map(|(i, item)| match names.get(item) {
+                    Some(qualified) if i == 0 => qualified.clone(),
+                    _ => qualify(names, item),
+                })
+                .collect(),
+        ),
+        token => token.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::{Engine, EngineOptions};
+    use crate::testing::{eval, TempDir};
+
+    #[test]
+    fn imports_load_files_once_as_modules() {
+        let dir = TempDir::new("import");
+        let lib = dir.join("lib");
+        dir.write(
+            "lib/family.lx",
+            "(.dec (parent-of tom ann)) (.def (has-child X) (parent-of X ann))
+             (.dec (size 2)) (.set loads (+ (.get loads) 1))",
+        );
+        dir.write("lib/shop.lx", "(.dec (size 10))");
+        dir.write("a.lx", "(.import b)");
+        dir.write("b.lx", "(.import a)");
+        dir.write(
+            "main.lx",
+            "(.import \"lib/family.lx\") (.import shop) (.import \"lib/family\" :only (size))",
+        );
+
+        let mut engine = Engine::with_options(EngineOptions {
+            search_path: vec![lib.clone()],
+            ..EngineOptions::default()
+        })
+        .unwrap();
+        eval(&mut engine, "(.set loads 0)");
+        engine.eval_file(dir.join("main.lx")).unwrap();
+
+        assert_eq!(
+            eval(
+                &mut engine,
+                "(family/size 2) (shop/size 10) (size 2) (size 10)"
+            ),
+            "(true true true (size 10))"
+        );
+        assert_eq!(
+            eval(&mut engine, "(family/has-child tom) (parent-of tom ann)"),
+            "(true (parent-of tom ann))"
+        );
+        assert_eq!(eval(&mut engine, "(.get loads)"), "(1)");
+
+        // a name is only ever imported from one module
+        let res = eval(&mut engine, "(.import shop :only (size))");
+        assert!(
+            res.contains("size is already imported from family"),
+            "{}",
+            res
+        );
+        assert_eq!(
+            eval(&mut engine, "(.import family :only (size)) (size 10)"),
+            "(true (size 10))"
+        );
+
+        let res = engine.eval_file(dir.join("a.lx")).unwrap_err().to_string();
+        assert!(res.contains("import cycle"), "{}", res);
+        let res = eval(&mut engine, "(.import nowhere)");
+        assert!(res.contains("module \"nowhere\" not found"), "{}", res);
+    }
+
+    #[test]
+    fn private_definitions_stay_in_their_file() {
+        let dir = TempDir::new("private");
+        dir.write(
+            "arith.lx",
+            "(.defp (double X) (* X 2)) (.def (quad X) (double (double X)))",
+        );
+        dir.write(
+            "geo.lx",
+            "(.export (area)) (.def (area X) (square X)) (.def (square X) (* X X))",
+        );
+
+        let mut engine = Engine::new();
+        let dir = dir.path().display().to_string();
+        eval(
+            &mut engine,
+            &format!("(.import \"{0}/arith\") (.import \"{0}/geo\")", dir),
+        );
+        assert_eq!(
+            eval(&mut engine, "(arith/quad 3) (geo/area 3) (double 3)"),
+            "(12 9 (double 3))"
+        );
+
+        let res = eval(&mut engine, "(arith/double 3)");
+        assert!(
+            res.starts_with("arith/double is private to module \"arith\""),
+            "{}",
+            res
+        );
+        assert_eq!(
+            eval(
+                &mut engine,
+                "(.try (geo/square 3) (.catch (.error private-definition M) caught))"
+            ),
+            "(caught)"
+        );
+        let res = eval(
+            &mut engine,
+            &format!("(.import \"{}/geo\" :only (square))", dir),
+        );
+        assert!(res.starts_with("geo/square is private"), "{}", res);
+    }
+}
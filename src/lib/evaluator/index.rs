@@ -0,0 +1,398 @@
+use crate::evaluator::definition::Definition;
+use crate::evaluator::lazy::is_thunk;
+use crate::token::Token;
+
+use std::collections::{HashMap, HashSet};
+
+// what a position of a signature can be looked up by. variables and wildcards
+// have no key since they match anything
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Key {
+    Atom(Token),
+    List(usize),
+}
+
+fn key(token: &Token) -> Option<Key> {
+    match token {
+        Token::Var(_) | Token::Wildcard(_) => None,
+        Token::Lst(lst) => Some(Key::List(lst.len())),
+        t => Some(Key::Atom(t.clone())),
+    }
+}
+
+// the buckets of an index holding positions among the definitions laid out
+// rather than ids, which is what an image keeps of the index
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Layout {
+    pub others: Vec<usize>,
+    pub lengths: Vec<LengthLayout>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LengthLayout {
+    pub length: usize,
+    pub any_head: Vec<usize>,
+    pub heads: Vec<HeadLayout>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeadLayout {
+    pub head: Key,
+    pub any_first: Vec<usize>,
+    pub by_first: Vec<(Key, Vec<usize>)>,
+}
+
+impl Layout {
+    fn buckets(&self) -> impl Iterator<Item = &Vec<usize>> {
+        std::iter::once(&self.others).chain(self.lengths.iter().flat_map(|length| {
+            std::iter::once(&length.any_head).chain(length.heads.iter().flat_map(|head| {
+                std::iter::once(&head.any_first).chain(head.by_first.iter().map(|(_, ids)| ids))
+            }))
+        }))
+    }
+
+    // whether the buckets hold each of count positions once, in order
+    pub fn is_valid(&self, count: usize) -> bool {
+        let mut seen = vec![false; count];
+        for bucket in self.buckets() {
+            if bucket.windows(2).any(|pair| pair[0] >= pair[1]) {
+                return false;
+            }
+            for position in bucket {
+                match seen.get_mut(*position) {
+                    Some(seen) if !*seen => *seen = true,
+                    _ => return false,
+                }
+            }
+        }
+        seen.into_iter().all(|seen| seen)
+    }
+}
+
+#[derive(Clone, Default)]
+struct HeadBucket {
+    // signatures of a single element, or whose first argument is a variable
+    any_first: Vec<usize>,
+    by_first: HashMap<Key, Vec<usize>>,
+}
+
+#[derive(Clone, Default)]
+struct LengthBucket {
+    any_head: Vec<usize>,
+    by_head: HashMap<Key, HeadBucket>,
+}
+
+// definitions indexed by list length, head and first argument. lookups give
+// back the candidates in the order they were added, which is the order they
+// are tried in
+#[derive(Clone, Default)]
+pub struct DefinitionIndex {
+    definitions: Vec<Definition>,
+    // signatures that aren't lists
+    others: Vec<usize>,
+    by_length: HashMap<usize, LengthBucket>,
+    special_heads: HashSet<Token>,
+    native_heads: HashSet<Token>,
+}
+
+impl DefinitionIndex {
+    pub fn push(&mut self, definition: Definition) {
+        let id = self.definitions.len();
+
+        match &definition.signature {
+            Token::Lst(sig) if !sig.is_empty() => {
+                let bucket = self.by_length.entry(sig.len()).or_default();
+                match key(&sig[0]) {
+                    None => bucket.any_head.push(id),
+                    Some(head) => {
+                        let head = bucket.by_head.entry(head).or_default();
+                        match sig.get(1).and_then(key) {
+                            None => head.any_first.push(id),
+                            Some(first) => head.by_first.entry(first).or_default().push(id),
+                        }
+                    }
+                }
+
+                if definition.is_special() {
+                    self.special_heads.insert(sig[0].clone());
+                }
+                if definition.is_native() {
+                    self.native_heads.insert(sig[0].clone());
+                }
+            }
+            _ => self.others.push(id),
+        }
+
+        self.definitions.push(definition);
+    }
+
+    // adds definitions laid out as layout says, which must be valid for
+    // them, without looking their signatures up again
+    pub fn extend(&mut self, definitions: Vec<Definition>, layout: &Layout) {
+        let base = self.definitions.len();
+        let ids = |positions: &[usize]| -> Vec<usize> {
+            positions.iter().map(|position| base + position).collect()
+        };
+
+        self.others.extend(ids(&layout.others));
+        for length in &layout.lengths {
+            let bucket = self.by_length.entry(length.length).or_default();
+            bucket.any_head.extend(ids(&length.any_head));
+            for head in &length.heads {
+                let bucket = bucket.by_head.entry(head.head.clone()).or_default();
+                bucket.any_first.extend(ids(&head.any_first));
+                for (first, positions) in &head.by_first {
+                    let bucket = bucket.by_first.entry(first.clone()).or_default();
+                    bucket.extend(ids(positions));
+                }
+            }
+        }
+
+        for definition in definitions {
+            if let Token::Lst(sig) = &definition.signature {
+                if definition.is_special() {
+                    self.special_heads.insert(sig[0].clone());
+                }
+                if definition.is_native() {
+                    self.native_heads.insert(sig[0].clone());
+                }
+            }
+            self.definitions.push(definition);
+        }
+    }
+
+    // the buckets holding the definitions for which keep holds, numbered by
+    // their position among them. buckets are in the order of their first
+    // definition, so the same definitions are always laid out alike
+    pub fn layout<F: Fn(&Definition) -> bool>(&self, keep: F) -> Layout {
+        let mut next = 0;
+        let positions: Vec<Option<usize>> = self
+            .definitions
+            .iter()
+            .map(|definition| {
+                keep(definition).then(|| {
+                    next += 1;
+                    next - 1
+                })
+            })
+            .collect();
+        let kept =
+            |ids: &[usize]| -> Vec<usize> { ids.iter().filter_map(|id| positions[*id]).collect() };
+        // buckets by their first position, leaving out the empty ones
+        fn sorted<T>(buckets: Vec<(Option<usize>, T)>) -> Vec<T> {
+            let mut buckets: Vec<(usize, T)> = buckets
+                .into_iter()
+                .filter_map(|(first, bucket)| Some((first?, bucket)))
+                .collect();
+            buckets.sort_by_key(|(first, _)| *first);
+            buckets.into_iter().map(|(_, bucket)| bucket).collect()
+        }
+
+        let lengths = self
+            .by_length
+            .iter()
+            .map(|(length, bucket)| {
+                let heads = bucket
+                    .by_head
+                    .iter()
+                    .map(|(head, bucket)| {
+                        let by_first = sorted(
+                            bucket
+                                .by_first
+                                .iter()
+                                .map(|(first, ids)| {
+                                    let ids = kept(ids);
+                                    (ids.first().copied(), (first.clone(), ids))
+                                })
+                                .collect(),
+                        );
+                        let any_first = kept(&bucket.any_first);
+                        let first =
+                            lowest(any_first.first(), by_first.first().map(|(_, ids)| ids[0]));
+                        let head = HeadLayout {
+                            head: head.clone(),
+                            any_first,
+                            by_first,
+                        };
+                        (first, head)
+                    })
+                    .collect();
+                let any_head = kept(&bucket.any_head);
+                let heads = sorted(heads);
+                let first = lowest(any_head.first(), heads.first().map(first_of_head));
+                let length = LengthLayout {
+                    length: *length,
+                    any_head,
+                    heads,
+                };
+                (first, length)
+            })
+            .collect();
+
+        Layout {
+            others: kept(&self.others),
+            lengths: sorted(lengths),
+        }
+    }
+
+    pub fn get(&self, id: usize) -> &Definition {
+        &self.definitions[id]
+    }
+
+    // keeps the definitions for which keep holds, in the same order. the
+    // index is built again, which is fine for something as rare as this
+    pub fn retain<F: FnMut(&Definition) -> bool>(&mut self, mut keep: F) {
+        let definitions = std::mem::take(&mut self.definitions);
+        *self = DefinitionIndex::default();
+        for definition in definitions {
+            if keep(&definition) {
+                self.push(definition);
+            }
+        }
+    }
+
+    // every definition, in the order they were added
+    pub fn iter(&self) -> impl Iterator<Item = &Definition> {
+        self.definitions.iter()
+    }
+
+    // ids of the definitions that may match token, in order
+    pub fn candidates(&self, token: &Token) -> Vec<usize> {
+        let mut sources: Vec<&[usize]> = vec![&self.others];
+
+        if let Token::Lst(lst) = token {
+            if let Some(bucket) = self.by_length.get(&lst.len()) {
+                sources.push(&bucket.any_head);
+
+                if let Some(head) = lst
+                    .first()
+                    .and_then(key)
+                    .and_then(|k| bucket.by_head.get(&k))
+                {
+                    sources.push(&head.any_first);
+                    match lst.get(1) {
+                        // a thunk may turn out to be any value
+                        Some(first) if is_thunk(first) => {
+                            sources.extend(head.by_first.values().map(|ids| &ids[..]))
+                        }
+                        first => {
+                            if let Some(ids) =
+                                first.and_then(key).and_then(|k| head.by_first.get(&k))
+                            {
+                                sources.push(ids);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        merge(sources)
+    }
+
+    pub fn is_special_head(&self, head: &Token) -> bool {
+        self.special_heads.contains(head)
+    }
+
+    pub fn is_native_head(&self, head: &Token) -> bool {
+        self.native_heads.contains(head)
+    }
+
+    // numbers of arguments taken by the definitions headed by head
+    pub fn arities(&self, head: &Token) -> Vec<usize> {
+        let head = match key(head) {
+            Some(head) => head,
+            None => return vec![],
+        };
+
+        let mut arities: Vec<usize> = self
+            .by_length
+            .iter()
+            .filter(|(_, bucket)| bucket.by_head.contains_key(&head))
+            .map(|(length, _)| length - 1)
+            .collect();
+        arities.sort_unstable();
+        arities
+    }
+}
+
+fn lowest(a: Option<&usize>, b: Option<usize>) -> Option<usize> {
+    match (a.copied(), b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+fn first_of_head(head: &HeadLayout) -> usize {
+    let first = head.by_first.first().map(|(_, ids)| ids[0]);
+    lowest(head.any_first.first(), first).unwrap_or_default()
+}
+
+// merges sorted lists of ids into one
+fn merge(sources: Vec<&[usize]>) -> Vec<usize> {
+    let mut sources: Vec<&[usize]> = sources.into_iter().filter(|s| !s.is_empty()).collect();
+    if sources.len() == 1 {
+        return sources[0].to_vec();
+    }
+
+    let mut ids: Vec<usize> = Vec::with_capacity(sources.iter().map(|s| s.len()).sum());
+    while let Some((i, _)) = sources
+        .iter()
+        .enumerate()
+        .filter_map(|(i, source)| source.first().map(|id| (i, *id)))
+        .min_by_key(|(_, id)| *id)
+    {
+        ids.push(sources[i][0]);
+        sources[i] = &sources[i][1..];
+    }
+
+    ids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::definition::def::fact;
+    use crate::parser::Parser;
+    use crate::symbol::{sym, Interner};
+
+    fn tokens(symbols: &Interner, source: &str) -> Vec<Token> {
+        let parser = Parser::new(symbols.clone());
+        match parser.parse_with_spans(format!("({})", source)) {
+            Ok((Token::Lst(forms), _)) => forms.to_vec(),
+            res => panic!("{:?}", res),
+        }
+    }
+
+    fn index(symbols: &Interner, source: &str) -> DefinitionIndex {
+        let mut index = DefinitionIndex::default();
+        for signature in tokens(symbols, source) {
+            index.push(fact(&signature, false));
+        }
+        index
+    }
+
+    #[test]
+    fn candidates_are_looked_up_by_length_head_and_first_argument() {
+        let symbols = Interner::new();
+        let index = index(&symbols, "(f a 1) (f X 2) (f b 3) (Y a 4) (g a 5) (f a)");
+        let calls = tokens(&symbols, "(f a 9) (f c 9) (g b 9) (f a) (f b) (h)");
+
+        let found: Vec<Vec<usize>> = calls.iter().map(|call| index.candidates(call)).collect();
+        assert_eq!(
+            found,
+            [vec![0, 1, 3], vec![1, 3], vec![3], vec![5], vec![], vec![]]
+        );
+        assert_eq!(index.arities(&symbols.str("f")), [1, 2]);
+    }
+
+    #[test]
+    fn a_thunk_first_argument_may_be_anything() {
+        let symbols = Interner::new();
+        let index = index(&symbols, "(f a 1) (f X 2) (f b 3) (g a 4)");
+
+        let thunk = Token::list(vec![Token::Keyword(sym::THUNK), Token::Number(0.0)]);
+        let call = Token::list(vec![symbols.str("f"), thunk, Token::Number(9.0)]);
+        assert_eq!(index.candidates(&call), [0, 1, 2]);
+    }
+}
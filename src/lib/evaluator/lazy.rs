@@ -0,0 +1,334 @@
+use crate::error::EvalError;
+use crate::evaluator::{match_token, EvalResult, Evaluator, Step};
+use crate::symbol::sym;
+use crate::token::Token;
+
+use std::collections::{HashMap, HashSet};
+
+// a lazy call passes its arguments on unevaluated, each list as a thunk
+// (#thunk N): the form at N of the table, evaluated the first time a clause
+// needs its value and then kept. a clause needs an argument where its head
+// has something other than a variable, so (.def (if-true true X) X) evaluates
+// the condition and never the branch unless it is the result.
+//
+// only clauses are lazy. other definitions get their arguments with every
+// thunk in them forced, and so does whatever a program evaluates to, but for
+// the tails of streams: (.stream-cons H T) evaluates H and delays T, which is
+// only forced by what reads the stream
+#[derive(Debug, Clone, Default)]
+pub struct Thunks {
+    // each form and, once forced, its value, by id
+    forms: HashMap<usize, (Token, Option<Token>)>,
+    // the id of the next form. ids aren't given out twice, so a thunk still
+    // held where it wasn't looked for is left unforced once dropped, rather
+    // than taken for another
+    next: usize,
+}
+
+impl Thunks {
+    fn push(&mut self, form: Token) -> usize {
+        let id = self.next;
+        self.next += 1;
+        self.forms.insert(id, (form, None));
+        id
+    }
+
+    fn get(&self, id: usize) -> Option<&(Token, Option<Token>)> {
+        self.forms.get(&id)
+    }
+
+    // drops every thunk but those in roots, and those in the values of the
+    // ones kept, or in their forms while they aren't forced
+    pub fn retain_reachable<'a>(&mut self, roots: impl Iterator<Item = &'a Token>) {
+        let mut reachable = HashSet::new();
+        let mut pending: Vec<Token> = roots.cloned().collect();
+        while let Some(token) = pending.pop() {
+            match thunk_id(&token) {
+                Some(id) if reachable.insert(id) => match self.forms.get(&id) {
+                    Some((_, Some(value))) => pending.push(value.clone()),
+                    Some((form, None)) => pending.push(form.clone()),
+                    None => {}
+                },
+                Some(_) => {}
+                None => {
+                    if let Token::Lst(lst) = &token {
+                        pending.extend(
+                            lst.iter()
+                                .filter(|item| matches!(item, Token::Lst(_)))
+                                .cloned(),
+                        )
+                    }
+                }
+            }
+        }
+        self.forms.retain(|id, _| reachable.contains(id));
+    }
+
+    // the forms of the thunks not yet forced
+    pub fn unforced(&self) -> impl Iterator<Item = &Token> {
+        self.forms
+            .values()
+            .filter(|(_, value)| value.is_none())
+            .map(|(form, _)| form)
+    }
+}
+
+fn thunk_id(token: &Token) -> Option<usize> {
+    match token {
+        Token::Lst(lst) => match &**lst {
+            [Token::Keyword(sym::THUNK), Token::Number(id)] => Some(*id as usize),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn stream_cell(token: &Token) -> Option<(&Token, &Token)> {
+    match token {
+        Token::Lst(lst) => match &**lst {
+            [Token::Keyword(sym::STREAM_CONS), head, tail] => Some((head, tail)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+pub fn is_thunk(token: &Token) -> bool {
+    thunk_id(token).is_some()
+}
+
+pub fn has_thunks(token: &Token) -> bool {
+    match token {
+        Token::Lst(lst) => is_thunk(token) || lst.iter().any(has_thunks),
+        _ => false,
+    }
+}
+
+impl Evaluator {
+    // whether the arguments of a call are left for the clause matching it to
+    // evaluate, for every call or those matching a (.lazy pattern)
+    pub(super) fn is_lazy_call(&self, lst: &std::sync::Arc<[Token]>) -> bool {
+        let state = &self.curr_state;
+        state.lazy
+            || (!state.lazy_patterns.is_empty()
+                && state
+                    .lazy_patterns
+                    .iter()
+                    .any(|pattern| match_token(pattern, &Token::Lst(lst.clone()))))
+    }
+
+    // dispatches the call with its head evaluated and its arguments delayed
+    pub(super) fn dispatch_lazy(&mut self, lst: &[Token]) -> Result<Step, EvalError> {
+        let mut call = Vec::with_capacity(lst.len());
+        for (i, token) in lst.iter().enumerate() {
+            call.push(match i {
+                0 => self.eval_token(token.clone())?,
+                _ => self.delay(token.clone()),
+            });
+        }
+        self.dispatch(Token::list(call))
+    }
+
+    // token with what the definition id needs of it forced
+    pub(super) fn force_for(&mut self, id: usize, token: Token) -> EvalResult {
+        let def = self.curr_state.definition.get(id);
+        match def.body() {
+            Some(_) => {
+                let signature = def.signature.clone();
+                self.force_needed(&signature, token)
+            }
+            None => self.force_all(&token),
+        }
+    }
+
+    // (.stream-cons H T), the value of H, quoted if it is a list so the cell
+    // can be evaluated again, and T delayed
+    pub(super) fn eval_stream_cons(&mut self, lst: &[Token]) -> Result<Step, EvalError> {
+        let (head, tail) = match lst {
+            [_, head, tail] => (head, tail),
+            _ => {
+                return Err(EvalError::ArityMismatch {
+                    definition: ".stream-cons".to_owned(),
+                    expected: vec![2],
+                    found: lst.len() - 1,
+                })
+            }
+        };
+
+        let head = match self.eval_token(head.clone())? {
+            value @ Token::Lst(_) => Token::list(vec![Token::Keyword(sym::QUOTE), value]),
+            value => value,
+        };
+        let tail = self.delay(tail.clone());
+        Ok(Step::Done(Token::list(vec![lst[0].clone(), head, tail])))
+    }
+
+    fn delay(&mut self, token: Token) -> Token {
+        match token {
+            Token::Lst(_) if !is_thunk(&token) => {
+                let id = self.curr_state.thunks.push(token);
+                Token::list(vec![Token::Keyword(sym::THUNK), Token::Number(id as f32)])
+            }
+            token => token,
+        }
+    }
+
+    // (#thunk N) evaluated
+    pub(super) fn eval_thunk(&mut self, lst: &[Token]) -> Result<Step, EvalError> {
+        let token = Token::list(lst.to_vec());
+        self.force(&token).map(Step::Done)
+    }
+
+    fn force(&mut self, token: &Token) -> EvalResult {
+        let id = match thunk_id(token) {
+            Some(id) => id,
+            None => return Ok(token.clone()),
+        };
+
+        let form = match self.curr_state.thunks.get(id) {
+            Some((_, Some(value))) => return Ok(value.clone()),
+            Some((form, None)) => form.clone(),
+            None => return Ok(token.clone()),
+        };
+        let value = self.eval_token(form)?;
+        if let Some((_, forced)) = self.curr_state.thunks.forms.get_mut(&id) {
+            *forced = Some(value.clone());
+        }
+        Ok(value)
+    }
+
+    // token with every thunk in it forced, and every thunk in their values,
+    // but for the tails of streams that haven't been yet
+    pub(super) fn force_all(&mut self, token: &Token) -> EvalResult {
+        if !has_thunks(token) {
+            return Ok(token.clone());
+        }
+
+        match token {
+            _ if is_thunk(token) => {
+                let value = self.force(token)?;
+                self.force_all(&value)
+            }
+            // the cells of a stream are followed in a loop, as it can have
+            // been read far
+            _ if stream_cell(token).is_some() => {
+                let mut heads = vec![];
+                let mut rest = token.clone();
+                let end = loop {
+                    let (head, tail) = match stream_cell(&rest) {
+                        Some(cell) => cell,
+                        None => break self.force_all(&rest)?,
+                    };
+                    heads.push(self.force_all(head)?);
+                    rest = match thunk_id(tail).and_then(|id| self.curr_state.thunks.get(id)) {
+                        Some((_, Some(value))) => value.clone(),
+                        Some((_, None)) => break tail.clone(),
+                        None => tail.clone(),
+                    };
+                };
+                Ok(heads.into_iter().rev().fold(end, |tail, head| {
+                    Token::list(vec![Token::Keyword(sym::STREAM_CONS), head, tail])
+                }))
+            }
+            Token::Lst(lst) => Ok(Token::list(
+                lst.iter()
+                    .map(|item| self.force_all(item))
+                    .collect::<Result<Vec<Token>, EvalError>>()?,
+            )),
+            token => Ok(token.clone()),
+        }
+    }
+
+    // token with the thunks forced where signature needs their values to be
+    // matched, from left to right and stopping at the first argument that
+    // doesn't match
+    pub(super) fn force_needed(&mut self, signature: &Token, token: Token) -> EvalResult {
+        let token = match signature {
+            Token::Var(_) | Token::Wildcard(_) => return Ok(token),
+            _ => self.force(&token)?,
+        };
+
+        match (signature, &token) {
+            (Token::Lst(sig), Token::Lst(lst)) if sig.len() == lst.len() => {
+                let mut forced = lst.to_vec();
+                for (sig, item) in sig.iter().zip(forced.iter_mut()) {
+                    *item = self.force_needed(sig, item.clone())?;
+                    if !match_token(sig, item) {
+                        break;
+                    }
+                }
+                Ok(Token::list(forced))
+            }
+            _ => Ok(token),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Thunks;
+    use crate::engine::{Engine, EngineOptions};
+    use crate::symbol::sym;
+    use crate::testing::eval;
+    use crate::token::Token;
+
+    fn thunk(id: usize) -> Token {
+        Token::list(vec![Token::Keyword(sym::THUNK), Token::Number(id as f32)])
+    }
+
+    #[test]
+    fn only_reachable_thunks_are_kept() {
+        let mut thunks = Thunks::default();
+        let forced = thunks.push(Token::list(vec![]));
+        let tail = thunks.push(Token::list(vec![]));
+        let unread = thunks.push(Token::list(vec![thunk(tail)]));
+        let dropped = thunks.push(Token::list(vec![]));
+        thunks.forms.get_mut(&forced).unwrap().1 = Some(Token::list(vec![
+            Token::Keyword(sym::STREAM_CONS),
+            Token::Number(1.0),
+            thunk(unread),
+        ]));
+
+        let roots = [Token::list(vec![Token::Number(0.0), thunk(forced)])];
+        thunks.retain_reachable(roots.iter());
+        assert!(thunks.get(forced).is_some());
+        assert!(thunks.get(unread).is_some());
+        assert!(thunks.get(tail).is_some());
+        assert!(thunks.get(dropped).is_none());
+
+        thunks.retain_reachable([].iter());
+        assert!(thunks.forms.is_empty());
+        // ids aren't reused
+        assert_eq!(thunks.push(Token::list(vec![])), 4);
+    }
+
+    #[test]
+    fn lazy_calls_evaluate_arguments_once_needed() {
+        let program = "(.def (if-true true X) X)
+             (.def (if-true false X) skipped)
+             (.def (ones) (cons 1 (ones)))
+             (.def (take 0 L) nil)
+             (.def (take N (cons H T)) (cons H (take (- N 1) T)))
+             (.def (tick) (.transaction (.set n (+ (.get n) 1)) 5))
+             (.def (twice X) (+ X X))
+             (.set n 0)";
+
+        let mut engine = Engine::new();
+        eval(&mut engine, program);
+        eval(&mut engine, "(.lazy (if-true C X)) (.lazy (cons H T))");
+        assert_eq!(
+            eval(&mut engine, "(if-true false (.throw boom)) (take 2 (ones))"),
+            "(skipped (cons 1 (cons 1 nil)))"
+        );
+
+        let mut engine = Engine::with_options(EngineOptions {
+            lazy: true,
+            ..EngineOptions::default()
+        })
+        .unwrap();
+        eval(&mut engine, program);
+        assert_eq!(eval(&mut engine, "(take 1 (ones))"), "((cons 1 nil))");
+        // the thunk of (tick) is forced once and its value kept
+        assert_eq!(eval(&mut engine, "(twice (tick)) (.get n)"), "(10 1)");
+    }
+}
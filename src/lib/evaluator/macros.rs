@@ -0,0 +1,166 @@
+use crate::error::EvalError;
+use crate::evaluator::definition::def::{match_variable, replace_variable, variable_names};
+use crate::evaluator::{match_token, Evaluator};
+use crate::printer::render_token;
+use crate::symbol::{sym, Interner, Symbol};
+use crate::token::Token;
+
+use std::collections::{HashMap, HashSet};
+
+// (.defmacro (unless Cond Then) `(.try ...)) is tried on a call before its
+// arguments are evaluated. the variables of the signature are bound to the
+// forms given, quoted, and the template is evaluated to the form the call is
+// replaced by, which is evaluated in turn.
+//
+// the other variables of the template are renamed apart on each expansion,
+// so the X of a (.def (,Name X) ...) it writes is never one the caller passed
+// in, nor that of another expansion
+#[derive(Debug, Clone)]
+struct Macro {
+    signature: Token,
+    template: Token,
+    // the position of the macro among all those defined
+    id: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Macros {
+    by_head: HashMap<Token, Vec<Macro>>,
+    // how many macros have been defined
+    defined: usize,
+    // how many calls have been expanded, which tells renamed variables apart
+    // when printed
+    expanded: usize,
+}
+
+impl Macros {
+    // later definitions of a macro are tried after the earlier ones, as the
+    // clauses of a definition are
+    pub fn define(&mut self, signature: Token, template: Token) -> bool {
+        let head = match &signature {
+            Token::Lst(lst) if !lst.is_empty() => lst[0].clone(),
+            _ => return false,
+        };
+
+        self.defined += 1;
+        self.by_head.entry(head).or_default().push(Macro {
+            signature,
+            template,
+            id: self.defined,
+        });
+        true
+    }
+
+    // the template of m with the variables it introduces given names no
+    // other expansion has
+    fn fresh_template(&mut self, symbols: &Interner, m: &Macro) -> Token {
+        self.expanded += 1;
+        let params: HashSet<Symbol> = variable_names(&m.signature).into_iter().collect();
+        let mut renamed = HashMap::new();
+        for name in variable_names(&m.template) {
+            if !params.contains(&name) && !renamed.contains_key(&name) {
+                let fresh = format!("{}#{}", symbols.resolve(name), self.expanded);
+                renamed.insert(name, Token::Var(symbols.fresh(&fresh)));
+            }
+        }
+
+        rename(&m.template, &renamed)
+    }
+
+    // the signature and template of every macro, in the order they were
+    // defined
+    pub fn definitions(&self) -> Vec<(Token, Token)> {
+        let mut macros: Vec<&Macro> = self.by_head.values().flatten().collect();
+        macros.sort_by_key(|m| m.id);
+        macros
+            .into_iter()
+            .map(|m| (m.signature.clone(), m.template.clone()))
+            .collect()
+    }
+
+    fn find(&self, call: &Token) -> Option<&Macro> {
+        let head = match call {
+            Token::Lst(lst) => lst.first()?,
+            _ => return None,
+        };
+
+        self.by_head
+            .get(head)?
+            .iter()
+            .find(|m| match_token(&m.signature, call))
+    }
+}
+
+// unlike replace_variable, this also renames the variables of quoted forms
+fn rename(token: &Token, renamed: &HashMap<Symbol, Token>) -> Token {
+    match token {
+        Token::Var(name) => renamed.get(name).cloned().unwrap_or_else(|| token.clone()),
+        Token::Lst(lst) => Token::list(lst.iter().map(|item| rename(item, renamed)).collect()),
+        token => token.clone(),
+    }
+}
+
+impl Evaluator {
+    // the form a call to a macro expands to, or None if lst isn't one
+    pub(super) fn expand_macro(&mut self, lst: &[Token]) -> Result<Option<Token>, EvalError> {
+        let call = Token::list(lst.to_vec());
+        let m = match self.curr_state.macros.find(&call) {
+            Some(m) => m.clone(),
+            None => return Ok(None),
+        };
+        self.budget.step()?;
+
+        let symbols = self.curr_state.symbols.clone();
+        let mut variables = match_variable(&m.signature, &call);
+        for form in variables.values_mut() {
+            *form = Token::list(vec![Token::Keyword(sym::QUOTE), form.clone()]);
+        }
+        let template = self.curr_state.macros.fresh_template(&symbols, &m);
+        let template =
+            replace_variable(&template, &variables).map_err(|name| EvalError::UnboundVariable {
+                name: symbols.resolve(name).to_string(),
+                definition: render_token(&symbols, &m.signature),
+            })?;
+
+        self.eval_token(template).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+    use crate::testing::eval;
+
+    #[test]
+    fn macros_expand_unevaluated_forms_hygienically() {
+        let mut engine = Engine::new();
+        eval(
+            &mut engine,
+            "(.defmacro (.rule Name :if Param :then Result) `(.def (,Name ,Param) ,Result))
+             (.defmacro (.adder Name Amount) `(.def (,Name X) (+ X ,Amount)))
+             (.defmacro (unless Cond Then) `(.try ,Cond (.catch _ ,Then)))",
+        );
+
+        assert_eq!(
+            eval(&mut engine, "(.rule double :if N :then (* N 2)) (double 4)"),
+            "(true 8)"
+        );
+        assert_eq!(
+            eval(&mut engine, "(unless (/ 1 0) recovered)"),
+            "(recovered)"
+        );
+
+        // the X of .adder isn't the one passed to it
+        eval(&mut engine, "(.adder add-two 2) (.adder add-x X)");
+        assert_eq!(eval(&mut engine, "(add-two 1)"), "(3)");
+        let res = eval(&mut engine, "(add-x 1)");
+        assert!(res.starts_with("unbound variable \"X\""), "{}", res);
+
+        // the variables a macro introduces are renamed again on each
+        // expansion, so no two of the forms it writes share them
+        eval(&mut engine, "(.defmacro (pair) `(p X))");
+        let first = eval(&mut engine, "(pair)");
+        assert!(first.starts_with("((p X#"), "{}", first);
+        assert_ne!(first, eval(&mut engine, "(pair)"));
+    }
+}
@@ -1,118 +1,796 @@
+pub mod budget;
 pub mod definition;
+mod import;
+mod index;
+mod lazy;
+mod macros;
+mod solve;
+mod special;
+pub mod table;
+pub mod trace;
+pub mod vm;
 
+use budget::{Budget, CancellationToken};
 use definition::Definition;
 use definition::Module;
+use index::DefinitionIndex;
+pub(crate) use index::{HeadLayout, Key, Layout, LengthLayout};
+use lazy::{has_thunks, Thunks};
+use macros::Macros;
+use solve::Searches;
+use table::Table;
+use trace::{Backtrace, Frame};
 
-use crate::lib::error::EvalError;
-use crate::lib::token::Token;
+use crate::database::Change;
+use crate::error::{EvalError, LexError, ModuleError};
+use crate::parser::Spans;
+use crate::printer::render_token;
+use crate::symbol::{Interner, Symbol};
+use crate::token::{Span, Token};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
+/// What a form, or a handler, evaluates to.
 pub type EvalResult = Result<Token, EvalError>;
 
+/// The definitions and everything else an evaluation reads and changes, handed
+/// to each handler.
 #[derive(Clone)]
 pub struct EvalState {
-    definition: Vec<Definition>,
-    variables: HashMap<String, Token>,
+    definition: DefinitionIndex,
     return_value: Option<Token>,
+    call_stack: Vec<Frame>,
+    span: Option<Span>,
+    backtrace: Option<Backtrace>,
+    table: Table,
+    // the engine's interner, which every symbol met during evaluation is from
+    symbols: Interner,
+    // whether .def and .dec compile their clauses for the vm
+    compile: bool,
+    // what .set stored
+    values: HashMap<Token, Token>,
+    // changes to the clauses not yet written to the engine's database
+    changes: Vec<Change>,
+    // in the order they were loaded, so each comes after its dependencies
+    modules: Vec<Arc<dyn Module>>,
+    // the files being evaluated, the one importing the next
+    files: Vec<PathBuf>,
+    // where .import looks after the directory of the importing file
+    search_path: Vec<PathBuf>,
+    // the files imported and the modules they were loaded as
+    imports: HashMap<PathBuf, Arc<str>>,
+    // the names imported unqualified and the modules they are from
+    imported_names: HashMap<Symbol, Arc<str>>,
+    // the qualified names of the private definitions of imported files
+    private: HashSet<Token>,
+    // the entry files of the packages depended on, by name
+    packages: HashMap<Arc<str>, PathBuf>,
+    // what .defmacro defined
+    macros: Macros,
+    // whether every call is lazy, or only those matching one of the patterns
+    lazy: bool,
+    lazy_patterns: Vec<Token>,
+    // those of lazy_patterns given by .lazy rather than by a module
+    declared_lazy: Vec<Token>,
+    thunks: Thunks,
+    // the searches of the .solutions streams not read to their end
+    searches: Searches,
 }
 
 impl EvalState {
-    pub fn new() -> Self {
+    pub(crate) fn new(symbols: Interner) -> Self {
         EvalState {
-            definition: vec![],
-            variables: HashMap::new(),
+            definition: DefinitionIndex::default(),
             return_value: None,
+            call_stack: vec![],
+            span: None,
+            backtrace: None,
+            table: Table::default(),
+            symbols,
+            compile: false,
+            values: HashMap::new(),
+            changes: vec![],
+            modules: vec![],
+            files: vec![],
+            search_path: vec![],
+            imports: HashMap::new(),
+            imported_names: HashMap::new(),
+            private: HashSet::new(),
+            packages: HashMap::new(),
+            macros: Macros::default(),
+            lazy: false,
+            lazy_patterns: vec![],
+            declared_lazy: vec![],
+            thunks: Thunks::default(),
+            searches: Searches::default(),
         }
     }
+
+    /// Adds a definition after those already there.
+    pub fn add_definition(&mut self, definition: Definition) {
+        self.definition.push(definition);
+        self.table.invalidate();
+    }
+
+    /// The interner every symbol met during the evaluation comes from.
+    pub fn symbols(&self) -> &Interner {
+        &self.symbols
+    }
+
+    // removes the clauses whose head matches pattern, telling whether there
+    // were any. definitions of modules are left alone
+    pub(crate) fn retract(&mut self, pattern: &Token) -> bool {
+        let before = self.definition.iter().count();
+        self.definition.retain(|def| {
+            def.body().is_none() || def.module().is_some() || !match_token(pattern, &def.signature)
+        });
+        self.table.invalidate();
+
+        self.definition.iter().count() != before
+    }
+
+    fn module(&self, name: &str) -> Option<Arc<dyn Module>> {
+        self.modules
+            .iter()
+            .find(|module| module.name() == name)
+            .cloned()
+    }
+
+    fn remove_module(&mut self, name: &str) {
+        self.modules.retain(|module| module.name() != name);
+        self.definition.retain(|def| def.module() != Some(name));
+        self.table.invalidate();
+    }
+
+    // makes the calls matching pattern lazy, as .lazy does
+    pub(crate) fn declare_lazy(&mut self, pattern: Token) {
+        self.lazy_patterns.push(pattern.clone());
+        self.declared_lazy.push(pattern);
+    }
+
+    // keeps track of a change to write to the database, if there is one
+    pub(crate) fn record(&mut self, change: Change) {
+        self.changes.push(change);
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            definition: self.definition.clone(),
+            table: self.table.clone(),
+            values: self.values.clone(),
+            changes: self.changes.len(),
+            modules: self.modules.clone(),
+            imports: self.imports.clone(),
+            imported_names: self.imported_names.clone(),
+            private: self.private.clone(),
+            macros: self.macros.clone(),
+            lazy_patterns: self.lazy_patterns.clone(),
+            declared_lazy: self.declared_lazy.clone(),
+        }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.definition = snapshot.definition;
+        self.table = snapshot.table;
+        self.values = snapshot.values;
+        self.changes.truncate(snapshot.changes);
+        self.modules = snapshot.modules;
+        self.imports = snapshot.imports;
+        self.imported_names = snapshot.imported_names;
+        self.private = snapshot.private;
+        self.macros = snapshot.macros;
+        self.lazy_patterns = snapshot.lazy_patterns;
+        self.declared_lazy = snapshot.declared_lazy;
+    }
+}
+
+/// What `.def`, `.dec`, `.set` and tabling can change, leaving out the call
+/// stack and the other traces of the evaluation in progress.
+#[derive(Clone)]
+pub struct Snapshot {
+    definition: DefinitionIndex,
+    table: Table,
+    values: HashMap<Token, Token>,
+    changes: usize,
+    modules: Vec<Arc<dyn Module>>,
+    imports: HashMap<PathBuf, Arc<str>>,
+    imported_names: HashMap<Symbol, Arc<str>>,
+    private: HashSet<Token>,
+    macros: Macros,
+    lazy_patterns: Vec<Token>,
+    declared_lazy: Vec<Token>,
+}
+
+// whether value has the shape of signature, in which variables and wildcards
+// stand for anything
+pub fn match_token(signature: &Token, value: &Token) -> bool {
+    match (signature, value) {
+        (Token::Lst(a), Token::Lst(b)) => {
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| match_token(a, b))
+        }
+        (Token::Var(_), _) => true,
+        (Token::Str(a), Token::Str(b)) => a == b,
+        (Token::Keyword(a), Token::Keyword(b)) => a == b,
+        (Token::Number(a), Token::Number(b)) => a == b,
+        (Token::Bool(a), Token::Bool(b)) => a == b,
+        (Token::BinaryOp(a), Token::BinaryOp(b)) => a == b,
+        (Token::UnaryOp(a), Token::UnaryOp(b)) => a == b,
+        (Token::Wildcard(_), _) => true,
+        _ => false,
+    }
+}
+
+// nested evaluations allowed before giving up with a StackOverflow, low enough
+// to fit the 2MiB stack of a spawned thread even in a debug build
+pub const DEFAULT_MAX_DEPTH: usize = 500;
+
+// a reduction either produced a value or another expression to evaluate in
+// place of the current one
+enum Step {
+    Done(Token),
+    Tail(Token),
 }
 
 #[derive(Clone)]
 pub struct Evaluator {
     curr_state: EvalState,
+    depth: usize,
+    max_depth: usize,
+    budget: Budget,
+    // where the lists of the program being evaluated start
+    spans: Spans,
 }
 
 impl Evaluator {
-    pub fn new() -> Self {
+    pub fn new(symbols: Interner) -> Self {
         Evaluator {
-            curr_state: EvalState::new(),
+            curr_state: EvalState::new(symbols),
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            budget: Budget::default(),
+            spans: Spans::default(),
+        }
+    }
+
+    pub fn add_definition(&mut self, definition: Definition) {
+        self.curr_state.add_definition(definition);
+    }
+
+    // adds the definitions of module after those already there, once the
+    // modules it depends on are loaded
+    pub fn load_module(&mut self, module: Arc<dyn Module>) -> Result<(), LexError> {
+        let state = &mut self.curr_state;
+        let name = module.name().to_owned();
+        if state.module(&name).is_some() {
+            return Err(LexError::Module(ModuleError::AlreadyLoaded(name)));
+        }
+        if let Some(dependency) = module
+            .dependencies()
+            .iter()
+            .find(|dependency| state.module(dependency).is_none())
+        {
+            return Err(LexError::Module(ModuleError::MissingDependency {
+                module: name,
+                dependency: dependency.to_string(),
+            }));
+        }
+
+        let qualifier: Arc<str> = Arc::from(name.as_str());
+        for definition in module.load(&state.symbols) {
+            for definition in definition.loaded_by(&state.symbols, &qualifier) {
+                state.add_definition(definition);
+            }
+        }
+        state.modules.push(module.clone());
+
+        if let Err(err) = module.on_load(state) {
+            state.remove_module(&name);
+            return Err(LexError::EvalError(err, Backtrace::default()));
+        }
+        Ok(())
+    }
+
+    // removes the definitions of the module called name, unless another
+    // module depends on it
+    pub fn unload_module(&mut self, name: &str) -> Result<(), LexError> {
+        let state = &mut self.curr_state;
+        let module = match state.module(name) {
+            Some(module) => module,
+            None => return Err(LexError::Module(ModuleError::NotLoaded(name.to_owned()))),
+        };
+        if let Some(by) = state
+            .modules
+            .iter()
+            .find(|other| other.dependencies().contains(&name))
+        {
+            return Err(LexError::Module(ModuleError::RequiredBy {
+                module: name.to_owned(),
+                by: by.name().to_owned(),
+            }));
         }
+
+        module.on_unload(state);
+        state.remove_module(name);
+        Ok(())
     }
 
-    pub fn load<T: Module>(&mut self, _: T) {
-        for item in T::load() {
-            self.curr_state.definition.push(item);
+    pub fn modules(&self) -> impl Iterator<Item = &str> {
+        self.curr_state.modules.iter().map(|module| module.name())
+    }
+
+    // evaluates the top-level forms of a program, spans being where they and
+    // the lists in them start in the source
+    pub fn eval_program(&mut self, program: Token, spans: Spans) -> EvalResult {
+        self.curr_state.call_stack.clear();
+        self.curr_state.backtrace = None;
+        self.depth = 0;
+        self.budget.start();
+        self.curr_state.table.reset();
+        // stored streams keep the thunks of their tails
+        let state = &mut self.curr_state;
+        state.thunks.retain_reachable(state.values.values());
+        state.searches.retain_reachable(state.thunks.unforced());
+
+        let lst = match program {
+            Token::Lst(lst) => lst,
+            t => return self.eval_token(t),
+        };
+
+        // the addresses of the lists are only those of this program while it
+        // is being evaluated
+        self.spans = spans;
+        let res = self.eval_forms(&lst);
+        self.spans = Spans::default();
+        res
+    }
+
+    fn eval_forms(&mut self, lst: &[Token]) -> EvalResult {
+        let mut forms: Vec<Token> = vec![];
+        for (i, token) in lst.iter().enumerate() {
+            self.curr_state.span = self.spans.forms.get(i).copied();
+            let res = self
+                .eval_token(token.clone())
+                .and_then(|value| self.force_all(&value));
+            self.curr_state.span = None;
+            forms.push(res?);
         }
+
+        let base = self.curr_state.call_stack.len();
+        let step = self.dispatch(Token::list(forms));
+        self.run(base, step)
+    }
+
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    pub fn set_max_steps(&mut self, max_steps: Option<u64>) {
+        self.budget.max_steps = max_steps;
+    }
+
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.budget.timeout = timeout;
+    }
+
+    pub fn set_search_path(&mut self, search_path: Vec<PathBuf>) {
+        self.curr_state.search_path = search_path;
+    }
+
+    pub fn add_package(&mut self, name: &str, entry: PathBuf) {
+        self.curr_state.packages.insert(Arc::from(name), entry);
+    }
+
+    // the file the source evaluated next comes from, which is where it
+    // imports from, or None for the current directory
+    pub fn set_file(&mut self, file: Option<PathBuf>) {
+        self.curr_state.files = file.into_iter().collect();
+    }
+
+    pub fn set_lazy(&mut self, lazy: bool) {
+        self.curr_state.lazy = lazy;
+    }
+
+    pub fn set_compile(&mut self, compile: bool) {
+        self.curr_state.compile = compile;
+    }
+
+    pub fn set_cancellation_token(&mut self, cancellation: CancellationToken) {
+        self.budget.cancellation = cancellation;
+    }
+
+    // the clauses written with .def and .dec, as (head body) pairs in the
+    // order they are tried
+    pub fn clauses(&self) -> Vec<(Token, Token)> {
+        self.curr_state
+            .definition
+            .iter()
+            .filter(|def| is_clause(def))
+            .filter_map(|def| def.body().map(|body| (def.signature.clone(), body.clone())))
+            .collect()
+    }
+
+    // the qualified names of the private definitions of imported files,
+    // which are kept along with the clauses
+    pub fn private_names(&self) -> Vec<Token> {
+        let mut names: Vec<Token> = self.curr_state.private.iter().cloned().collect();
+        names.sort_by_cached_key(|name| render_token(&self.curr_state.symbols, name));
+        names
+    }
+
+    pub fn add_private_name(&mut self, name: Token) {
+        self.curr_state.private.insert(name);
+    }
+
+    // the macros given by .defmacro, as (signature template) pairs in the
+    // order they were defined
+    pub fn macros(&self) -> Vec<(Token, Token)> {
+        self.curr_state.macros.definitions()
+    }
+
+    pub fn add_macro(&mut self, signature: Token, template: Token) {
+        self.curr_state.macros.define(signature, template);
+    }
+
+    // how the index buckets the clauses, by their position in clauses()
+    pub(crate) fn clause_layout(&self) -> Layout {
+        self.curr_state.definition.layout(is_clause)
+    }
+
+    // the changes to the clauses made since the last call
+    pub fn take_changes(&mut self) -> Vec<Change> {
+        std::mem::take(&mut self.curr_state.changes)
     }
 
-    fn eval_lst(&mut self, input: Vec<Token>) -> EvalResult {
-        let lst = input
-            .into_iter()
-            .map(|token| self.eval_token(token).unwrap())
+    pub fn retract(&mut self, pattern: &Token) {
+        self.curr_state.retract(pattern);
+    }
+
+    // adds a clause as .def would, or as .dec if its body is just true
+    pub fn add_clause(&mut self, head: &Token, body: &Token) {
+        let definition = self.clause(head, body);
+        self.curr_state.add_definition(definition);
+    }
+
+    // adds clauses along with the layout clause_layout gave for them, which
+    // spares looking each of them up in the index
+    pub(crate) fn add_clauses(&mut self, clauses: &[(Token, Token)], layout: &Layout) {
+        let definitions = clauses
+            .iter()
+            .map(|(head, body)| self.clause(head, body))
             .collect();
+        self.curr_state.definition.extend(definitions, layout);
+        self.curr_state.table.invalidate();
+    }
 
-        let token = Token::Lst(lst);
+    fn clause(&self, head: &Token, body: &Token) -> Definition {
+        let compile = self.curr_state.compile;
+        match body {
+            Token::Bool(true) => definition::def::fact(head, compile),
+            body => definition::def::rule(head, body, compile),
+        }
+    }
 
-        for def in self.curr_state.definition.clone() {
-            if self.match_token(def.signature.clone(), token.clone()) {
-                let res = def.run(&mut self.curr_state, token.clone());
-                if let Ok(Token::Unknown) = res {
-                    break;
-                }
+    // the patterns given to .table
+    pub fn tabled_patterns(&self) -> &[Token] {
+        &self.curr_state.table.patterns
+    }
+
+    pub fn add_tabled_pattern(&mut self, pattern: Token) {
+        self.curr_state.table.patterns.push(pattern);
+    }
 
-                let ret = match res {
-                    Ok(t) => self.eval_token(t),
-                    Err(err) => Err(err),
-                };
-                return ret;
+    // the patterns given to .lazy
+    pub fn declared_lazy_patterns(&self) -> &[Token] {
+        &self.curr_state.declared_lazy
+    }
+
+    pub fn add_lazy_pattern(&mut self, pattern: Token) {
+        self.curr_state.declare_lazy(pattern);
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        self.curr_state.snapshot()
+    }
+
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        self.curr_state.restore(snapshot);
+    }
+
+    // the call stack at the time the last evaluation failed
+    pub fn take_backtrace(&mut self) -> Backtrace {
+        self.curr_state.backtrace.take().unwrap_or_default()
+    }
+
+    fn capture_backtrace(&mut self) {
+        if self.curr_state.backtrace.is_none() {
+            self.curr_state.backtrace = Some(Backtrace::capture(
+                &self.curr_state.symbols,
+                &self.curr_state.call_stack,
+            ));
+        }
+    }
+
+    // keeps reducing until a value is reached, so a definition whose body is
+    // a call to another definition doesn't grow the native stack. base is the
+    // height of the call stack before the first reduction
+    fn run(&mut self, base: usize, step: Result<Step, EvalError>) -> EvalResult {
+        let mut step = step;
+
+        let res = loop {
+            // the frame of a call replaced by its tail call is dropped once
+            // the tail call is matched, only the latest one stays on the stack
+            let top = self.curr_state.call_stack.len();
+            if top > base + 1 {
+                self.curr_state.call_stack.drain(base..top - 1);
             }
+
+            match step {
+                Ok(Step::Done(token)) => break Ok(token),
+                Ok(Step::Tail(Token::Lst(lst))) => step = self.reduce(lst),
+                Ok(Step::Tail(token)) => break Ok(token),
+                Err(err) => break Err(err),
+            }
+        };
+
+        if res.is_err() {
+            self.capture_backtrace();
         }
+        self.curr_state.call_stack.truncate(base);
 
-        Ok(token)
+        res
     }
 
-    fn match_token(&self, signature: Token, value: Token) -> bool {
-        match (signature, value) {
-            (Token::Lst(a), Token::Lst(b)) => {
-                if a.len() != b.len() {
-                    false
-                } else {
-                    let mut ret = true;
-                    for i in 0..a.len() {
-                        ret = ret && self.match_token(a[i].clone(), b[i].clone());
-                    }
+    fn reduce(&mut self, input: Arc<[Token]>) -> Result<Step, EvalError> {
+        if let Some(step) = self.eval_special_form(&input) {
+            return step;
+        }
 
-                    ret
-                }
+        // macros are expanded before the arguments are evaluated
+        if let Some(form) = self.expand_macro(&input)? {
+            return Ok(Step::Tail(form));
+        }
+
+        if self.is_special_call(&input) {
+            return self.dispatch(Token::Lst(input));
+        }
+
+        // only nested lists can evaluate to something else, a list of atoms
+        // is passed on as it is
+        if !input.iter().any(|token| matches!(token, Token::Lst(_))) {
+            return self.dispatch(Token::Lst(input));
+        }
+
+        if self.is_lazy_call(&input) {
+            return self.dispatch_lazy(&input);
+        }
+
+        // a loop rather than an iterator, whose adapters would each take a
+        // frame of the native stack for every nested call in debug builds
+        let mut lst = Vec::with_capacity(input.len());
+        for token in input.iter() {
+            lst.push(self.eval_token(token.clone())?);
+        }
+
+        self.dispatch(Token::list(lst))
+    }
+
+    fn dispatch(&mut self, token: Token) -> Result<Step, EvalError> {
+        if self.is_tabled(&token) {
+            return self.tabled_call(token);
+        }
+
+        self.dispatch_definition(token)
+    }
+
+    // runs the first definition matching token, leaving its result to be
+    // evaluated by the caller
+    fn dispatch_definition(&mut self, token: Token) -> Result<Step, EvalError> {
+        let mut token = token;
+        let mut lazy = has_thunks(&token);
+
+        for id in self.curr_state.definition.candidates(&token) {
+            if lazy {
+                token = self.force_for(id, token)?;
+                lazy = has_thunks(&token);
             }
-            (Token::Var(_), _) => true,
-            (Token::Str(a), Token::Str(b)) => a == b,
-            (Token::Keyword(a), Token::Keyword(b)) => a == b,
-            (Token::Number(a), Token::Number(b)) => a == b,
-            (Token::Bool(a), Token::Bool(b)) => a == b,
-            (Token::BinaryOp(a), Token::BinaryOp(b)) => a == b,
-            (Token::UnaryOp(a), Token::UnaryOp(b)) => a == b,
-            (Token::Wildcard(_), _) => true,
-            _ => false,
+
+            let def = self.curr_state.definition.get(id);
+            // compiled clauses match their head and bind its variables in
+            // one pass
+            let registers = match def.clause() {
+                Some(clause) => match clause.match_head(&token) {
+                    Some(registers) => Some(registers),
+                    None => continue,
+                },
+                None if match_token(&def.signature, &token) => None,
+                None => continue,
+            };
+
+            let def = def.clone();
+            self.budget.step()?;
+            self.curr_state.call_stack.push(Frame {
+                signature: def.signature.clone(),
+                input: token.clone(),
+                span: self.curr_state.span,
+            });
+
+            let res = match (def.clause(), registers) {
+                (Some(clause), Some(registers)) => {
+                    clause
+                        .build_body(&registers)
+                        .map_err(|name| EvalError::UnboundVariable {
+                            name: self.curr_state.symbols.resolve(name).to_string(),
+                            definition: render_token(&self.curr_state.symbols, &def.signature),
+                        })?
+                }
+                _ => def.run(&mut self.curr_state, token.clone())?,
+            };
+
+            return match res {
+                Token::Unknown => Ok(Step::Done(token)),
+                t => Ok(Step::Tail(t)),
+            };
         }
+
+        self.check_builtin_call(&token)?;
+
+        Ok(Step::Done(token))
     }
 
-    pub fn eval_token(&mut self, token: Token) -> EvalResult {
-        let mut child_eval = Evaluator::new();
+    fn is_special_call(&self, lst: &[Token]) -> bool {
+        let head = match lst.first() {
+            Some(head @ Token::Keyword(_)) => head,
+            _ => return false,
+        };
 
-        // child_eval.curr_state = self.curr_state.clone();
+        self.curr_state.definition.is_special_head(head)
+    }
 
-        let ret = match token {
-            Token::Lst(lst) => self.eval_lst(lst),
-            t => Ok(t),
+    // lists headed by a keyword, an operator or the name of a registered
+    // function are calls to a built-in or native definition, so failing to
+    // match one of those is an error rather than plain data. so is calling a
+    // private definition of an imported file from outside of it
+    fn check_builtin_call(&self, token: &Token) -> Result<(), EvalError> {
+        let lst = match token {
+            Token::Lst(lst) => lst,
+            _ => return Ok(()),
         };
 
-        // println!("current state: ");
-        // for item in self.curr_state.definition.clone() {
-        //     println!("{:?}", item.signature);
-        // }
-        // println!("end state ");
+        if let Some(head) = lst
+            .first()
+            .filter(|head| self.curr_state.private.contains(head))
+        {
+            let name = render_token(&self.curr_state.symbols, head);
+            return Err(EvalError::PrivateDefinition {
+                module: name.split('/').next().unwrap_or_default().to_owned(),
+                definition: name,
+            });
+        }
+
+        let head = match lst.first() {
+            Some(head @ (Token::Keyword(_) | Token::BinaryOp(_) | Token::UnaryOp(_))) => head,
+            Some(head) if self.curr_state.definition.is_native_head(head) => head,
+            _ => return Ok(()),
+        };
+
+        let arities = self.curr_state.definition.arities(head);
+        if arities.is_empty() {
+            return Ok(());
+        }
+
+        if !arities.contains(&(lst.len() - 1)) {
+            return Err(EvalError::ArityMismatch {
+                definition: render_token(&self.curr_state.symbols, head),
+                expected: arities,
+                found: lst.len() - 1,
+            });
+        }
+
+        Err(EvalError::NoMatchingDefinition {
+            definition: render_token(&self.curr_state.symbols, head),
+            input: render_token(&self.curr_state.symbols, token),
+        })
+    }
+
+    pub fn eval_token(&mut self, token: Token) -> EvalResult {
+        let lst = match token {
+            Token::Lst(lst) => lst,
+            t => return Ok(t),
+        };
+
+        if self.depth >= self.max_depth {
+            self.capture_backtrace();
+            return Err(EvalError::StackOverflow {
+                limit: self.max_depth,
+            });
+        }
+
+        // calls written in the source are located where they are written,
+        // the others where the call they are evaluated for is
+        let span = self.curr_state.span;
+        if let Some(at) = self.spans.of(&lst) {
+            self.curr_state.span = Some(at);
+        }
+
+        self.depth += 1;
+        let base = self.curr_state.call_stack.len();
+        let step = self.reduce(lst);
+        let res = self.run(base, step);
+        self.depth -= 1;
+        self.curr_state.span = span;
+
+        res
+    }
+}
+
+// a definition given by .def or .dec rather than by a module
+fn is_clause(definition: &Definition) -> bool {
+    definition.module().is_none() && definition.body().is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::{Engine, EngineOptions};
+    use crate::error::EvalError;
+    use crate::testing::eval_err;
+
+    #[test]
+    fn failures_are_typed_errors() {
+        let mut engine = Engine::new();
+        engine
+            .eval_str("(.def (f X) (g Y)) (.def (.one 1) 1)")
+            .unwrap();
+
+        assert!(matches!(
+            eval_err(&mut engine, "(f 1)"),
+            EvalError::UnboundVariable { name, definition } if name == "Y" && definition == "(f X)"
+        ));
+        assert!(matches!(
+            eval_err(&mut engine, "(+ 1 2 3)"),
+            EvalError::ArityMismatch { expected, found: 3, .. } if expected == [2]
+        ));
+        assert!(matches!(
+            eval_err(&mut engine, "(+ 1 a)"),
+            EvalError::TypeMismatch { found, .. } if found == "a"
+        ));
+        assert!(matches!(
+            eval_err(&mut engine, "(/ 1 0)"),
+            EvalError::DivisionByZero { input, .. } if input == "(/ 1 0)"
+        ));
+        assert!(matches!(
+            eval_err(&mut engine, "(.one 2)"),
+            EvalError::NoMatchingDefinition { definition, .. } if definition == ".one"
+        ));
+
+        // none of them leaves the engine unusable
+        let value = engine.eval_str("(.one 1)").unwrap();
+        assert_eq!(engine.render(&value), "(1)");
+    }
+
+    #[test]
+    fn tail_calls_run_flat_and_other_calls_are_limited() {
+        let mut engine = Engine::with_options(EngineOptions {
+            max_depth: 50,
+            ..EngineOptions::default()
+        })
+        .unwrap();
+        engine
+            .eval_str(
+                "(.def (count 0) done)
+                 (.def (count N) (count (- N 1)))
+                 (.def (deep 0) 0)
+                 (.def (deep N) (+ 1 (deep (- N 1))))",
+            )
+            .unwrap();
 
-        ret
+        let value = engine.eval_str("(count 20000) (deep 40)").unwrap();
+        assert_eq!(engine.render(&value), "(done 40)");
+        assert!(matches!(
+            eval_err(&mut engine, "(deep 100)"),
+            EvalError::StackOverflow { limit: 50 }
+        ));
     }
 }
@@ -0,0 +1,349 @@
+use crate::error::EvalError;
+use crate::evaluator::{is_clause, Evaluator, Step};
+use crate::symbol::{sym, Symbol};
+use crate::token::Token;
+
+use std::collections::{HashMap, HashSet};
+
+// (.solutions Pattern) is the instances of Pattern the clauses prove, as a
+// stream, found by resolution, depth first and trying clauses in the order
+// they were given. a clause whose head unifies with a goal proves it when
+// its body does: as a goal in turn if it calls a clause, and otherwise by
+// evaluating to true once its variables are bound. the variables of a clause
+// are renamed apart each time it is used.
+//
+// the tail of each cell is (#search N), the search the cell was found by,
+// kept at N of the table to go on from there once the tail is forced. as in
+// any depth first search, a goal proven only through itself, such as
+// (loop X) by (.def (loop X) (loop X)), is looked for until the budget of
+// the evaluation runs out, or forever if it has none
+#[derive(Debug, Clone, Default)]
+pub struct Searches {
+    searches: HashMap<usize, Search>,
+    // ids aren't given out twice, as for thunks
+    next: usize,
+}
+
+impl Searches {
+    // drops the searches no unforced tail in roots can go on with
+    pub fn retain_reachable<'a>(&mut self, roots: impl Iterator<Item = &'a Token>) {
+        let mut reachable = HashSet::new();
+        let mut pending: Vec<&Token> = roots.collect();
+        while let Some(token) = pending.pop() {
+            if let Token::Lst(lst) = token {
+                match &**lst {
+                    [Token::Keyword(sym::SEARCH), Token::Number(id)] => {
+                        reachable.insert(*id as usize);
+                    }
+                    items => pending.extend(items),
+                }
+            }
+        }
+        self.searches.retain(|id, _| reachable.contains(id));
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Search {
+    query: Term,
+    // the goals left to prove, each along with the bindings made so far, the
+    // next to try last. None is a goal proven
+    pending: Vec<(Option<Term>, Bindings)>,
+    // the id of the next variable renamed
+    next: usize,
+}
+
+#[derive(Debug, Clone)]
+enum Term {
+    // a variable, by id, and the token it was renamed from
+    Var(usize, Token),
+    Lst(Vec<Term>),
+    Atom(Token),
+}
+
+type Bindings = HashMap<usize, Term>;
+
+// token as a term, its variables numbered from next on. each wildcard is a
+// variable of its own
+fn term(token: &Token, names: &mut HashMap<Symbol, usize>, next: &mut usize) -> Term {
+    let mut fresh = || {
+        *next += 1;
+        *next - 1
+    };
+
+    match token {
+        Token::Var(name) => Term::Var(*names.entry(*name).or_insert_with(fresh), token.clone()),
+        Token::Wildcard(_) => Term::Var(fresh(), token.clone()),
+        Token::Lst(lst) => Term::Lst(lst.iter().map(|item| term(item, names, next)).collect()),
+        atom => Term::Atom(atom.clone()),
+    }
+}
+
+fn walk<'a>(term: &'a Term, bindings: &'a Bindings) -> &'a Term {
+    let mut term = term;
+    while let Term::Var(id, _) = term {
+        match bindings.get(id) {
+            Some(value) => term = value,
+            None => break,
+        }
+    }
+    term
+}
+
+// whether the variable id is in term. one isn't bound to a term holding it,
+// which would make the term endless
+fn occurs(id: usize, term: &Term, bindings: &Bindings) -> bool {
+    let mut pending = vec![term];
+    while let Some(term) = pending.pop() {
+        match walk(term, bindings) {
+            Term::Var(other, _) if *other == id => return true,
+            Term::Lst(items) => pending.extend(items),
+            _ => {}
+        }
+    }
+    false
+}
+
+fn unify(a: &Term, b: &Term, bindings: &mut Bindings) -> bool {
+    let mut pending = vec![(a.clone(), b.clone())];
+    while let Some((a, b)) = pending.pop() {
+        let a = walk(&a, bindings).clone();
+        let b = walk(&b, bindings).clone();
+        match (&a, &b) {
+            (Term::Var(x, _), Term::Var(y, _)) if x == y => {}
+            (Term::Var(x, _), _) if !occurs(*x, &b, bindings) => {
+                bindings.insert(*x, b);
+            }
+            (_, Term::Var(y, _)) if !occurs(*y, &a, bindings) => {
+                bindings.insert(*y, a);
+            }
+            (Term::Lst(x), Term::Lst(y)) if x.len() == y.len() => {
+                pending.extend(x.iter().cloned().zip(y.iter().cloned()))
+            }
+            (Term::Atom(x), Term::Atom(y)) if x == y => {}
+            _ => return false,
+        }
+    }
+    true
+}
+
+// term with its bound variables replaced, and the others left as written
+fn resolve(term: &Term, bindings: &Bindings) -> Token {
+    match walk(term, bindings) {
+        Term::Var(_, token) | Term::Atom(token) => token.clone(),
+        Term::Lst(items) => Token::list(items.iter().map(|item| resolve(item, bindings)).collect()),
+    }
+}
+
+// whether a clause with head could be for goal, both being lists of the same
+// length headed by the same name, or the clause's by a variable
+fn same_shape(head: &Token, goal: &Token) -> bool {
+    match (head, goal) {
+        (Token::Lst(head), Token::Lst(goal)) => {
+            head.len() == goal.len()
+                && match head.first() {
+                    Some(Token::Var(_) | Token::Wildcard(_)) => true,
+                    name => name == goal.first(),
+                }
+        }
+        _ => false,
+    }
+}
+
+impl Evaluator {
+    // (.solutions Pattern)
+    pub(super) fn eval_solutions(&mut self, lst: &[Token]) -> Result<Step, EvalError> {
+        let pattern = match lst {
+            [_, pattern] => pattern,
+            _ => {
+                return Err(EvalError::ArityMismatch {
+                    definition: ".solutions".to_owned(),
+                    expected: vec![1],
+                    found: lst.len() - 1,
+                })
+            }
+        };
+
+        let mut next = 0;
+        let query = term(pattern, &mut HashMap::new(), &mut next);
+        let search = Search {
+            query: query.clone(),
+            pending: vec![(Some(query), Bindings::new())],
+            next,
+        };
+        let searches = &mut self.curr_state.searches;
+        let id = searches.next;
+        searches.next += 1;
+        searches.searches.insert(id, search);
+
+        self.eval_search(id)
+    }
+
+    // (#search N) evaluated: the next solution of the search at N, if it
+    // has one, followed by the search going on from there
+    pub(super) fn eval_search(&mut self, id: usize) -> Result<Step, EvalError> {
+        let mut search = match self.curr_state.searches.searches.remove(&id) {
+            Some(search) => search,
+            None => return Ok(Step::Done(Token::list(vec![]))),
+        };
+        // a search that failed is put back to go on from the goal it failed
+        // at, should the tail be forced again
+        let solution = self.search(&mut search);
+        if !matches!(solution, Ok(None)) {
+            self.curr_state.searches.searches.insert(id, search);
+        }
+        let solution = match solution? {
+            Some(solution) => solution,
+            None => return Ok(Step::Done(Token::list(vec![]))),
+        };
+
+        Ok(Step::Tail(Token::list(vec![
+            Token::Keyword(sym::STREAM_CONS),
+            Token::list(vec![Token::Keyword(sym::QUOTE), solution]),
+            Token::list(vec![Token::Keyword(sym::SEARCH), Token::Number(id as f32)]),
+        ])))
+    }
+
+    // the next solution of search, if there is one
+    fn search(&mut self, search: &mut Search) -> Result<Option<Token>, EvalError> {
+        while let Some((goal, bindings)) = search.pending.pop() {
+            let goal = match goal {
+                Some(goal) => goal,
+                None => return Ok(Some(resolve(&search.query, &bindings))),
+            };
+
+            if let Err(err) = self.budget.step() {
+                search.pending.push((Some(goal), bindings));
+                return Err(err);
+            }
+            let token = resolve(&goal, &bindings);
+            let definition = &self.curr_state.definition;
+            // the index can't narrow down a first argument yet unbound
+            let ids = match &token {
+                Token::Lst(lst)
+                    if matches!(lst.get(1), Some(Token::Var(_) | Token::Wildcard(_))) =>
+                {
+                    (0..definition.iter().count()).collect()
+                }
+                Token::Lst(_) => definition.candidates(&token),
+                _ => vec![],
+            };
+            let clauses: Vec<usize> = ids
+                .into_iter()
+                .filter(|id| {
+                    let def = definition.get(*id);
+                    is_clause(def) && same_shape(&def.signature, &token)
+                })
+                .collect();
+
+            if clauses.is_empty() {
+                // no clause is for it, so it holds if it evaluates to true,
+                // which it can't be while some variable in it is unbound
+                if !token.is_ground() {
+                    continue;
+                }
+                match self.eval_token(token) {
+                    Ok(Token::Bool(true)) => search.pending.push((None, bindings)),
+                    Ok(_) => {}
+                    Err(err) => {
+                        search.pending.push((Some(goal), bindings));
+                        return Err(err);
+                    }
+                }
+                continue;
+            }
+
+            let mut alternatives = vec![];
+            for id in clauses {
+                let def = definition.get(id);
+                let body = def.body().unwrap();
+
+                let mut names = HashMap::new();
+                let head = term(&def.signature, &mut names, &mut search.next);
+                let mut bindings = bindings.clone();
+                if unify(&head, &goal, &mut bindings) {
+                    alternatives.push((Some(term(body, &mut names, &mut search.next)), bindings));
+                }
+            }
+            search.pending.extend(alternatives.into_iter().rev());
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+    use crate::testing::eval;
+
+    #[test]
+    fn solutions_are_proven_through_rules() {
+        let mut engine = Engine::new();
+        eval(
+            &mut engine,
+            "(.dec (likes alice tea))
+             (.dec (likes bob coffee))
+             (.dec (likes carol tea))
+             (.dec (edge a b))
+             (.dec (edge b c))
+             (.def (path X Y) (edge X Y))",
+        );
+
+        assert_eq!(
+            eval(&mut engine, "(.take 5 (.solutions (likes Who tea)))"),
+            "(((likes alice tea) (likes carol tea)))"
+        );
+        assert_eq!(
+            eval(
+                &mut engine,
+                "(.take 5 (.solutions (path a Y))) (.take 5 (.solutions (path X c)))"
+            ),
+            "(((path a b)) ((path b c)))"
+        );
+    }
+
+    #[test]
+    fn goals_can_have_endless_solutions() {
+        let mut engine = Engine::new();
+        eval(&mut engine, "(.dec (nat 0)) (.def (nat (s N)) (nat N))");
+        assert_eq!(
+            eval(&mut engine, "(.take 3 (.solutions (nat X)))"),
+            "(((nat 0) (nat (s 0)) (nat (s (s 0)))))"
+        );
+    }
+
+    #[test]
+    fn streams_go_on_from_where_their_search_stopped() {
+        let mut engine = Engine::new();
+        let visit = "(.def (visit) (.transaction (.set n (+ (.get n) 1)) true))";
+        eval(&mut engine, &[visit, visit, visit, "(.set n 0)"].join(" "));
+
+        // each proof is looked for once, rather than again for each cell
+        assert_eq!(
+            eval(&mut engine, "(.take 3 (.solutions (visit))) (.get n)"),
+            "(((visit) (visit) (visit)) 3)"
+        );
+
+        eval(&mut engine, "(.set n 0) (.set s (.solutions (visit)))");
+        eval(&mut engine, "(.take 2 (.get s))");
+        assert_eq!(
+            eval(&mut engine, "(.take 3 (.get s)) (.get n)"),
+            "(((visit) (visit) (visit)) 3)"
+        );
+    }
+
+    #[test]
+    fn a_variable_is_never_bound_to_a_term_holding_it() {
+        let mut engine = Engine::new();
+        eval(&mut engine, "(.dec (eq Y Y))");
+        assert_eq!(
+            eval(&mut engine, "(.take 1 (.solutions (eq X (s X))))"),
+            "(())"
+        );
+        assert_eq!(
+            eval(&mut engine, "(.take 1 (.solutions (eq X (s Z))))"),
+            "(((eq (s Z) (s Z))))"
+        );
+    }
+}
@@ -0,0 +1,288 @@
+use crate::error::EvalError;
+use crate::evaluator::definition::def::{match_variable, replace_variable};
+use crate::evaluator::lazy::has_thunks;
+use crate::evaluator::{match_token, Evaluator, Step};
+use crate::printer::render_token;
+use crate::symbol::{sym, Interner};
+use crate::token::Token;
+
+// forms that need to decide themselves when their arguments are evaluated,
+// which a definition can't do since its handler only sees the EvalState
+impl Evaluator {
+    pub(super) fn eval_special_form(&mut self, lst: &[Token]) -> Option<Result<Step, EvalError>> {
+        match lst.first() {
+            Some(Token::Keyword(sym::TRY)) => Some(self.eval_try(lst)),
+            Some(Token::Keyword(sym::TRANSACTION)) => Some(self.eval_transaction(lst)),
+            Some(Token::Keyword(sym::IMPORT)) => Some(self.eval_import(lst)),
+            Some(Token::Keyword(sym::QUOTE)) => Some(quote(lst)),
+            Some(Token::Keyword(sym::QUASIQUOTE)) => Some(self.eval_quasiquote(lst)),
+            Some(Token::Keyword(sym::THUNK)) => Some(self.eval_thunk(lst)),
+            Some(Token::Keyword(sym::STREAM_CONS)) => Some(self.eval_stream_cons(lst)),
+            Some(Token::Keyword(sym::MATCH_CASE)) => Some(self.eval_match_case(lst)),
+            Some(Token::Keyword(sym::SOLUTIONS)) => Some(self.eval_solutions(lst)),
+            Some(Token::Keyword(sym::SEARCH)) => match lst {
+                [_, Token::Number(id)] => Some(self.eval_search(*id as usize)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    // (.try expr (.catch Pattern handler) ...)
+    fn eval_try(&mut self, lst: &[Token]) -> Result<Step, EvalError> {
+        let (expr, clauses) = match lst {
+            [_, expr, clauses @ ..] if !clauses.is_empty() => (expr, clauses),
+            _ => {
+                return Err(EvalError::ArityMismatch {
+                    definition: ".try".to_owned(),
+                    expected: vec![2],
+                    found: lst.len() - 1,
+                })
+            }
+        };
+
+        let mut catches: Vec<(&Token, &Token)> = vec![];
+        for clause in clauses {
+            match clause {
+                Token::Lst(catch) => match &**catch {
+                    [Token::Keyword(keyword), pattern, handler] if *keyword == sym::CATCH => {
+                        catches.push((pattern, handler))
+                    }
+                    _ => return Err(catch_mismatch(&self.curr_state.symbols, clause)),
+                },
+                _ => return Err(catch_mismatch(&self.curr_state.symbols, clause)),
+            }
+        }
+
+        let err = match self.eval_token(expr.clone()) {
+            Ok(token) => return Ok(Step::Done(token)),
+            Err(err) if !err.is_catchable() => return Err(err),
+            Err(err) => err,
+        };
+
+        let value = err.to_token(&self.curr_state.symbols);
+        for (pattern, handler) in catches {
+            if match_token(pattern, &value) {
+                self.curr_state.backtrace = None;
+
+                let variables = match_variable(pattern, &value);
+                let handler = replace_variable(handler, &variables).map_err(|name| {
+                    EvalError::UnboundVariable {
+                        name: self.curr_state.symbols.resolve(name).to_string(),
+                        definition: render_token(&self.curr_state.symbols, pattern),
+                    }
+                })?;
+
+                return Ok(Step::Tail(handler));
+            }
+        }
+
+        Err(err)
+    }
+}
+
+// (.transaction expr ...) evaluates each expr in turn and gives the value of
+// the last one. if any fails, whatever they defined or set is undone before
+// the error goes on
+impl Evaluator {
+    fn eval_transaction(&mut self, lst: &[Token]) -> Result<Step, EvalError> {
+        if lst.len() < 2 {
+            return Err(EvalError::ArityMismatch {
+                definition: ".transaction".to_owned(),
+                expected: vec![1],
+                found: lst.len() - 1,
+            });
+        }
+
+        let snapshot = self.snapshot();
+        let mut value = Token::Bool(true);
+        for expr in &lst[1..] {
+            value = match self.eval_token(expr.clone()) {
+                Ok(value) => value,
+                Err(err) => {
+                    self.restore(snapshot);
+                    return Err(err);
+                }
+            };
+        }
+
+        Ok(Step::Done(value))
+    }
+}
+
+// (.quote X), or 'X, is X as it is, so a list can be kept as data
+fn quote(lst: &[Token]) -> Result<Step, EvalError> {
+    match lst {
+        [_, value] => Ok(Step::Done(value.clone())),
+        _ => Err(EvalError::ArityMismatch {
+            definition: ".quote".to_owned(),
+            expected: vec![1],
+            found: lst.len() - 1,
+        }),
+    }
+}
+
+// (.quasiquote X), or `X, is X as it is but for ,Y in it, which is replaced
+// by the value of Y, and ,@Y, which is replaced by the elements of the list Y
+// evaluates to
+impl Evaluator {
+    fn eval_quasiquote(&mut self, lst: &[Token]) -> Result<Step, EvalError> {
+        match lst {
+            [_, template] => Ok(Step::Done(self.fill_template(template)?)),
+            _ => Err(EvalError::ArityMismatch {
+                definition: ".quasiquote".to_owned(),
+                expected: vec![1],
+                found: lst.len() - 1,
+            }),
+        }
+    }
+
+    fn fill_template(&mut self, template: &Token) -> Result<Token, EvalError> {
+        let lst = match template {
+            Token::Lst(lst) => lst,
+            atom => return Ok(atom.clone()),
+        };
+
+        match &**lst {
+            [Token::Keyword(sym::UNQUOTE), expr] => return self.eval_token(expr.clone()),
+            [Token::Keyword(sym::SPLICE), _] => {
+                return Err(EvalError::TypeMismatch {
+                    definition: ".unquote-splicing".to_owned(),
+                    expected: "an element of a list".to_owned(),
+                    found: render_token(&self.curr_state.symbols, template),
+                })
+            }
+            _ => {}
+        }
+
+        let mut filled = vec![];
+        for item in lst.iter() {
+            match item {
+                Token::Lst(splice) if matches!(&**splice, [Token::Keyword(sym::SPLICE), _]) => {
+                    match self.eval_token(splice[1].clone())? {
+                        Token::Lst(items) => filled.extend(items.iter().cloned()),
+                        value => {
+                            return Err(EvalError::TypeMismatch {
+                                definition: ".unquote-splicing".to_owned(),
+                                expected: "list".to_owned(),
+                                found: render_token(&self.curr_state.symbols, &value),
+                            })
+                        }
+                    }
+                }
+                item => filled.push(self.fill_template(item)?),
+            }
+        }
+
+        Ok(Token::list(filled))
+    }
+}
+
+// (.match-case expr (Pattern result) ...) evaluates expr and then the result
+// of the first arm whose pattern matches its value, with the variables of
+// the pattern bound to what they matched there
+impl Evaluator {
+    fn eval_match_case(&mut self, lst: &[Token]) -> Result<Step, EvalError> {
+        let (expr, arms) = match lst {
+            [_, expr, arms @ ..] if !arms.is_empty() => (expr, arms),
+            _ => {
+                return Err(EvalError::ArityMismatch {
+                    definition: ".match-case".to_owned(),
+                    expected: vec![2],
+                    found: lst.len() - 1,
+                })
+            }
+        };
+
+        let mut cases: Vec<(&Token, &Token)> = vec![];
+        for arm in arms {
+            match arm {
+                Token::Lst(case) if case.len() == 2 => cases.push((&case[0], &case[1])),
+                _ => {
+                    return Err(EvalError::TypeMismatch {
+                        definition: ".match-case".to_owned(),
+                        expected: "(Pattern result)".to_owned(),
+                        found: render_token(&self.curr_state.symbols, arm),
+                    })
+                }
+            }
+        }
+
+        let mut value = self.eval_token(expr.clone())?;
+        for (pattern, result) in cases {
+            if has_thunks(&value) {
+                value = self.force_needed(pattern, value)?;
+            }
+            if !match_token(pattern, &value) {
+                continue;
+            }
+
+            let variables = match_variable(pattern, &value);
+            let result = replace_variable(result, &variables).map_err(|name| {
+                EvalError::UnboundVariable {
+                    name: self.curr_state.symbols.resolve(name).to_string(),
+                    definition: render_token(&self.curr_state.symbols, pattern),
+                }
+            })?;
+
+            return Ok(Step::Tail(result));
+        }
+
+        Err(EvalError::NoMatchingCase {
+            input: render_token(&self.curr_state.symbols, &value),
+        })
+    }
+}
+
+fn catch_mismatch(symbols: &Interner, clause: &Token) -> EvalError {
+    EvalError::TypeMismatch {
+        definition: ".try".to_owned(),
+        expected: "(.catch Pattern handler)".to_owned(),
+        found: render_token(symbols, clause),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+    use crate::testing::eval;
+
+    #[test]
+    fn failed_transactions_roll_back() {
+        let mut engine = Engine::new();
+        eval(&mut engine, "(.set n 1)");
+
+        let res = eval(
+            &mut engine,
+            "(.transaction (.dec (seen a)) (.set n 2) (.throw no))",
+        );
+        assert!(res.starts_with("uncaught exception no"));
+        assert_eq!(eval(&mut engine, "(seen a) (.get n)"), "((seen a) 1)");
+
+        eval(&mut engine, "(.transaction (.dec (seen b)) (.set n 3))");
+        assert_eq!(eval(&mut engine, "(seen b) (.get n)"), "(true 3)");
+    }
+
+    #[test]
+    fn match_case_fails_when_no_arm_matches() {
+        let mut engine = Engine::new();
+        assert_eq!(
+            eval(
+                &mut engine,
+                "(.match-case (pair 1 2) ((pair A B) (+ A B)) (_ none))"
+            ),
+            "(3)"
+        );
+        assert_eq!(
+            eval(&mut engine, "(.match-case 5 ((pair A B) A))"),
+            "no case of .match-case matches 5"
+        );
+        assert_eq!(
+            eval(
+                &mut engine,
+                "(.try (.match-case 5 ((pair A B) A)) (.catch (.error Kind Msg) Kind))"
+            ),
+            "(no-matching-case)"
+        );
+    }
+}
@@ -0,0 +1,294 @@
+use crate::error::EvalError;
+use crate::evaluator::lazy::has_thunks;
+use crate::evaluator::{match_token, EvalResult, Evaluator, Step};
+use crate::token::Token;
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone)]
+pub enum TableEntry {
+    Complete(Token),
+    // the call is being evaluated, answer is the approximation handed out to
+    // recursive calls of itself and used tells whether one was
+    Evaluating { answer: Token, used: bool },
+    // evaluated using the approximation of the call at index leader on the
+    // table stack, so it is only final once that call is
+    Incomplete { answer: Token, leader: usize },
+}
+
+#[derive(Debug, Clone)]
+struct TableFrame {
+    call: Token,
+    // lowest frame whose approximation this evaluation relied on
+    min_dep: usize,
+    // incomplete answers to settle along with this frame
+    members: Vec<Token>,
+}
+
+// answers of tabled definitions, keyed by their ground calls
+#[derive(Clone, Default)]
+pub struct Table {
+    pub patterns: Vec<Token>,
+    pub entries: HashMap<Token, TableEntry>,
+    stack: Vec<TableFrame>,
+}
+
+impl Table {
+    pub fn complete_answers(&self) -> Vec<(Token, Token)> {
+        self.entries
+            .iter()
+            .filter_map(|(call, entry)| match entry {
+                TableEntry::Complete(answer) => Some((call.clone(), answer.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // answers depend on the definitions, so adding one invalidates them
+    pub fn invalidate(&mut self) {
+        if self.stack.is_empty() {
+            self.entries.clear();
+        }
+    }
+
+    // drops what an evaluation interrupted by an error left behind
+    pub fn reset(&mut self) {
+        self.stack.clear();
+        self.entries
+            .retain(|_, entry| matches!(entry, TableEntry::Complete(_)));
+    }
+
+    fn depends_on(&mut self, leader: usize) {
+        for frame in self.stack.iter_mut().skip(leader + 1) {
+            frame.min_dep = frame.min_dep.min(leader);
+        }
+    }
+
+    fn position(&self, call: &Token) -> Option<usize> {
+        self.stack.iter().position(|frame| &frame.call == call)
+    }
+
+    // the answer a call has so far, which for one still being evaluated
+    // makes its caller depend on it
+    fn lookup(&mut self, call: &Token) -> Option<Token> {
+        match self.entries.get_mut(call)? {
+            TableEntry::Complete(answer) => Some(answer.clone()),
+            TableEntry::Evaluating { answer, used } => {
+                *used = true;
+                let answer = answer.clone();
+                if let Some(leader) = self.position(call) {
+                    self.depends_on(leader);
+                }
+                Some(answer)
+            }
+            TableEntry::Incomplete { answer, leader } => {
+                let (answer, leader) = (answer.clone(), *leader);
+                self.depends_on(leader);
+                Some(answer)
+            }
+        }
+    }
+
+    fn begin(&mut self, call: &Token) -> usize {
+        let index = self.stack.len();
+        self.stack.push(TableFrame {
+            call: call.clone(),
+            min_dep: index,
+            members: vec![],
+        });
+        index
+    }
+
+    // hands answer to the recursive calls of the next iteration, which
+    // reevaluates the calls that relied on the previous one
+    fn iterate(&mut self, call: &Token, index: usize, answer: &Token) {
+        self.entries.insert(
+            call.clone(),
+            TableEntry::Evaluating {
+                answer: answer.clone(),
+                used: false,
+            },
+        );
+        for member in std::mem::take(&mut self.stack[index].members) {
+            self.entries.remove(&member);
+        }
+    }
+
+    // whether value has to be handed to another iteration
+    fn reused(&self, call: &Token, value: &Token, answer: &Token) -> bool {
+        matches!(
+            self.entries.get(call),
+            Some(TableEntry::Evaluating { used: true, .. }) if value != answer
+        )
+    }
+
+    fn settle(&mut self, call: &Token, index: usize, res: EvalResult) -> EvalResult {
+        let frame = self.stack.pop().unwrap();
+
+        let value = match res {
+            Ok(value) => value,
+            Err(err) => {
+                self.entries.remove(call);
+                for member in frame.members {
+                    self.entries.remove(&member);
+                }
+                return Err(err);
+            }
+        };
+
+        if frame.min_dep < index {
+            self.entries.insert(
+                call.clone(),
+                TableEntry::Incomplete {
+                    answer: value.clone(),
+                    leader: frame.min_dep,
+                },
+            );
+            let leader = &mut self.stack[frame.min_dep];
+            leader.members.push(call.clone());
+            leader.members.extend(frame.members);
+        } else {
+            for member in frame.members {
+                if let Some(TableEntry::Incomplete { answer, .. }) = self.entries.remove(&member) {
+                    self.entries.insert(member, TableEntry::Complete(answer));
+                }
+            }
+            self.entries
+                .insert(call.clone(), TableEntry::Complete(value.clone()));
+        }
+
+        Ok(value)
+    }
+}
+
+// the approximation a left recursive call starts from, which makes relations
+// evaluate to their least fixpoint
+fn bottom() -> Token {
+    Token::Bool(false)
+}
+
+impl Evaluator {
+    pub(super) fn is_tabled(&self, token: &Token) -> bool {
+        !self.curr_state.table.patterns.is_empty()
+            && token.is_ground()
+            && self
+                .curr_state
+                .table
+                .patterns
+                .iter()
+                .any(|pattern| match_token(pattern, token))
+    }
+
+    // token is a tabled call, answered from the table when it can be
+    pub(super) fn tabled_call(&mut self, token: Token) -> Result<Step, EvalError> {
+        // answers are kept by call, which needs the values of its arguments
+        let token = match has_thunks(&token) {
+            true => self.force_all(&token)?,
+            false => token,
+        };
+        if !self.is_tabled(&token) {
+            return self.dispatch_definition(token);
+        }
+
+        match self.curr_state.table.lookup(&token) {
+            Some(answer) => Ok(Step::Done(answer)),
+            None => self.eval_tabled_nested(&token).map(Step::Done),
+        }
+    }
+
+    // a tabled call is evaluated to its fixpoint before it returns, even in
+    // tail position, so it nests like any other call
+    fn eval_tabled_nested(&mut self, call: &Token) -> EvalResult {
+        if self.depth >= self.max_depth {
+            self.capture_backtrace();
+            return Err(EvalError::StackOverflow {
+                limit: self.max_depth,
+            });
+        }
+
+        self.depth += 1;
+        let res = self.eval_tabled(call);
+        self.depth -= 1;
+        res
+    }
+
+    // evaluates a call until the answer it produces is the one its recursive
+    // calls were given. the recursion goes through here, so the table keeping
+    // is left to the functions around it to keep this frame small
+    fn eval_tabled(&mut self, call: &Token) -> EvalResult {
+        let index = self.curr_state.table.begin(call);
+        let depth = self.depth;
+
+        let mut answer = bottom();
+        let res = loop {
+            self.curr_state.table.iterate(call, index, &answer);
+
+            let base = self.curr_state.call_stack.len();
+            let step = self.dispatch_definition(call.clone());
+            match self.run(base, step) {
+                Ok(value) if self.curr_state.table.reused(call, &value, &answer) => answer = value,
+                res => break res,
+            }
+            if let Err(err) = self.next_iteration() {
+                break Err(err);
+            }
+        };
+
+        self.depth = depth;
+        self.curr_state.table.settle(call, index, res)
+    }
+
+    // each iteration is a step, and a level deeper, so an answer that never
+    // settles, as that of (n a) with (.def (n X) (s (n X))), overflows as the
+    // call would without the table
+    fn next_iteration(&mut self) -> Result<(), EvalError> {
+        self.budget.step()?;
+        if self.depth >= self.max_depth {
+            self.capture_backtrace();
+            return Err(EvalError::StackOverflow {
+                limit: self.max_depth,
+            });
+        }
+
+        self.depth += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+    use crate::error::EvalError;
+    use crate::testing::{eval, eval_err};
+
+    #[test]
+    fn tabled_calls_are_limited_too() {
+        let mut engine = Engine::new();
+        eval(
+            &mut engine,
+            "(.table (down N))
+             (.def (down 0) 0)
+             (.def (down N) (down (- N 1)))",
+        );
+
+        assert!(matches!(
+            eval_err(&mut engine, "(down 100000)"),
+            EvalError::StackOverflow { .. }
+        ));
+        assert_eq!(eval(&mut engine, "(down 100)"), "(0)");
+    }
+
+    #[test]
+    fn answers_that_never_settle_overflow() {
+        let mut engine = Engine::new();
+        eval(&mut engine, "(.table (n X)) (.def (n X) (s (n X)))");
+        assert!(matches!(
+            eval_err(&mut engine, "(n a)"),
+            EvalError::StackOverflow { limit: 500 }
+        ));
+
+        // and the table is left as it was
+        eval(&mut engine, "(.def (m X) (s X)) (.table (m X))");
+        assert_eq!(eval(&mut engine, "(m a)"), "((s a))");
+    }
+}
@@ -0,0 +1,161 @@
+use crate::printer::render_token;
+use crate::symbol::Interner;
+use crate::token::{Span, Token};
+
+use std::fmt;
+
+// only this many groups of frames are printed, the innermost ones first
+const MAX_PRINTED_FRAMES: usize = 16;
+
+// a definition whose signature matched an input and is still being evaluated
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub signature: Token,
+    pub input: Token,
+    pub span: Option<Span>,
+}
+
+// consecutive frames of the same definition, as produced by recursion
+#[derive(Debug, Clone)]
+struct FrameGroup {
+    signature: String,
+    input: String,
+    span: Option<Span>,
+    depth: usize,
+    count: usize,
+}
+
+/// The definitions being evaluated when an evaluation failed, innermost
+/// first.
+#[derive(Debug, Clone, Default)]
+pub struct Backtrace {
+    groups: Vec<FrameGroup>,
+    depth: usize,
+}
+
+impl Backtrace {
+    // frames are given outermost first, as they are kept on the call stack
+    pub(crate) fn capture(symbols: &Interner, frames: &[Frame]) -> Self {
+        let mut groups: Vec<FrameGroup> = vec![];
+
+        for (depth, frame) in frames.iter().rev().enumerate() {
+            if let Some(group) = groups.last_mut() {
+                if group_matches(symbols, group, frame) {
+                    group.count += 1;
+                    continue;
+                }
+            }
+
+            groups.push(FrameGroup {
+                signature: render_token(symbols, &frame.signature),
+                input: render_token(symbols, &frame.input),
+                span: frame.span,
+                depth,
+                count: 1,
+            });
+        }
+
+        Backtrace {
+            groups,
+            depth: frames.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.depth == 0
+    }
+}
+
+fn group_matches(symbols: &Interner, group: &FrameGroup, frame: &Frame) -> bool {
+    group.span == frame.span && group.signature == render_token(symbols, &frame.signature)
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "backtrace (most recent call first):")?;
+
+        for group in self.groups.iter().take(MAX_PRINTED_FRAMES) {
+            write!(
+                f,
+                "\n  #{} {} <- {}",
+                group.depth, group.signature, group.input
+            )?;
+            if let Some(span) = group.span {
+                write!(f, " at line {}:{}", span.line, span.column)?;
+            }
+            if group.count > 1 {
+                write!(f, "\n     ... repeated {} more times", group.count - 1)?;
+            }
+        }
+
+        if self.groups.len() > MAX_PRINTED_FRAMES {
+            let shown: usize = self
+                .groups
+                .iter()
+                .take(MAX_PRINTED_FRAMES)
+                .map(|group| group.count)
+                .sum();
+            write!(f, "\n  ... {} more frames", self.depth - shown)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::error::LexError;
+
+    fn frame(symbols: &Interner, name: &str, n: f32, line: i32) -> Frame {
+        Frame {
+            signature: Token::list(vec![symbols.str(name), symbols.var("N")]),
+            input: Token::list(vec![symbols.str(name), Token::Number(n)]),
+            span: Some(Span { line, column: 1 }),
+        }
+    }
+
+    #[test]
+    fn recursion_is_folded_and_deep_stacks_capped() {
+        let symbols = Interner::new();
+        let mut frames = vec![frame(&symbols, "main", 0.0, 1)];
+        frames.extend((0..40).map(|n| frame(&symbols, "fib", n as f32, 2)));
+
+        let backtrace = Backtrace::capture(&symbols, &frames).to_string();
+        assert_eq!(
+            backtrace,
+            "backtrace (most recent call first):\
+             \n  #0 (fib N) <- (fib 39) at line 2:1\
+             \n     ... repeated 39 more times\
+             \n  #40 (main N) <- (main 0) at line 1:1"
+        );
+
+        // alternating definitions can't be folded, only the innermost are
+        // printed
+        let frames: Vec<Frame> = (0..40)
+            .map(|n| frame(&symbols, ["even", "odd"][n % 2], n as f32, 1))
+            .collect();
+        let backtrace = Backtrace::capture(&symbols, &frames).to_string();
+        assert_eq!(backtrace.lines().count(), 1 + MAX_PRINTED_FRAMES + 1);
+        assert!(backtrace.ends_with("\n  ... 24 more frames"));
+    }
+
+    #[test]
+    fn nested_calls_are_located_where_they_are_written() {
+        let mut engine = Engine::new();
+        engine
+            .eval_str("(.def (f 0) (/ 1 0)) (.def (f N) (+ 1 (f (- N 1))))")
+            .unwrap();
+
+        let backtrace = match engine.eval_str("(g 1\n   (f 2))") {
+            Err(LexError::EvalError(_, backtrace)) => backtrace.to_string(),
+            res => panic!("expected an error, got {:?}", res),
+        };
+        assert!(
+            backtrace.contains("(f N) <- (f 1) at line 2:4"),
+            "{}",
+            backtrace
+        );
+    }
+}
@@ -0,0 +1,476 @@
+use crate::evaluator::definition::def::variable_names;
+use crate::symbol::{sym, Symbol};
+use crate::token::Token;
+
+use std::collections::{HashMap, HashSet};
+
+// a clause (.def head body) is lowered to two instruction sequences. the head
+// reads the input term from left to right, depth first, the way a WAM get
+// sequence does, binding variables to registers. the body pushes the terms of
+// the result onto a stack, building lists bottom up
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    // the next term is a list of this length, its elements are read next
+    GetList(usize),
+    // the next term is this atom
+    GetConstant(Token),
+    // the next term goes to a register
+    GetVariable(usize),
+    // the next term is skipped, as wildcards are
+    UnifyVoid,
+    // nothing matches, for heads the tree walker can never match either
+    Fail,
+    // pushes a term holding no variable of the head
+    PutConstant(Token),
+    // pushes the term held by a register
+    PutValue(usize),
+    // pops this many terms and pushes them back as a list
+    PutList(usize),
+    // a variable of the body missing from the head
+    PutUnbound(Symbol),
+}
+
+#[derive(Debug, Clone)]
+pub struct Clause {
+    registers: usize,
+    head: Vec<Instruction>,
+    body: Vec<Instruction>,
+}
+
+impl Clause {
+    pub fn compile(head: &Token, body: &Token) -> Self {
+        let mut registers: HashMap<Symbol, usize> = HashMap::new();
+        let mut head_code = vec![];
+        compile_head(head, &mut registers, &mut head_code);
+
+        let mut body_code = vec![];
+        compile_body(body, &registers, &HashSet::new(), &mut body_code);
+
+        Clause {
+            registers: registers.len(),
+            head: head_code,
+            body: body_code,
+        }
+    }
+
+    // the registers bound by the head, or None if input doesn't match it
+    pub fn match_head(&self, input: &Token) -> Option<Vec<Token>> {
+        let mut registers: Vec<Token> = vec![Token::Unknown; self.registers];
+        // terms left to read in each list entered so far
+        let mut lists: Vec<(&[Token], usize)> = vec![(std::slice::from_ref(input), 0)];
+
+        for instruction in &self.head {
+            while matches!(lists.last(), Some((terms, i)) if *i == terms.len()) {
+                lists.pop();
+            }
+            let (terms, i) = lists.last_mut()?;
+            let term = &terms[*i];
+            *i += 1;
+
+            match instruction {
+                Instruction::GetList(len) => match term {
+                    Token::Lst(lst) if lst.len() == *len => lists.push((lst, 0)),
+                    _ => return None,
+                },
+                Instruction::GetConstant(constant) => {
+                    if !same_atom(constant, term) {
+                        return None;
+                    }
+                }
+                Instruction::GetVariable(register) => registers[*register] = term.clone(),
+                Instruction::UnifyVoid => {}
+                _ => return None,
+            }
+        }
+
+        Some(registers)
+    }
+
+    // the result of the clause for the registers bound by its head, or the
+    // first variable of the body that the head doesn't bind
+    pub fn build_body(&self, registers: &[Token]) -> Result<Token, Symbol> {
+        let mut stack: Vec<Token> = vec![];
+
+        for instruction in &self.body {
+            match instruction {
+                Instruction::PutConstant(constant) => stack.push(constant.clone()),
+                Instruction::PutValue(register) => stack.push(registers[*register].clone()),
+                Instruction::PutList(len) => {
+                    let items = stack.split_off(stack.len() - len);
+                    stack.push(Token::list(items));
+                }
+                Instruction::PutUnbound(name) => return Err(*name),
+                _ => {}
+            }
+        }
+
+        Ok(stack.pop().unwrap_or(Token::Unknown))
+    }
+}
+
+// the atoms match_token compares equal
+fn same_atom(a: &Token, b: &Token) -> bool {
+    match (a, b) {
+        (Token::Str(a), Token::Str(b)) => a == b,
+        (Token::Keyword(a), Token::Keyword(b)) => a == b,
+        (Token::Number(a), Token::Number(b)) => a == b,
+        (Token::Bool(a), Token::Bool(b)) => a == b,
+        (Token::BinaryOp(a), Token::BinaryOp(b)) => a == b,
+        (Token::UnaryOp(a), Token::UnaryOp(b)) => a == b,
+        _ => false,
+    }
+}
+
+// like match_variable, a variable seen twice in a head isn't checked for
+// equality, its last occurrence wins
+fn compile_head(
+    token: &Token,
+    registers: &mut HashMap<Symbol, usize>,
+    code: &mut Vec<Instruction>,
+) {
+    match token {
+        Token::Lst(lst) => {
+            code.push(Instruction::GetList(lst.len()));
+            for item in lst.iter() {
+                compile_head(item, registers, code);
+            }
+        }
+        Token::Var(name) => {
+            let next = registers.len();
+            let register = *registers.entry(*name).or_insert(next);
+            code.push(Instruction::GetVariable(register));
+        }
+        Token::Wildcard(_) => code.push(Instruction::UnifyVoid),
+        Token::Unknown => code.push(Instruction::Fail),
+        atom => code.push(Instruction::GetConstant(atom.clone())),
+    }
+}
+
+// shadowed are the variables bound by an enclosing (.catch Pattern handler)
+// or arm of a .match-case, which replace_variable leaves for the form to
+// replace
+fn compile_body(
+    token: &Token,
+    registers: &HashMap<Symbol, usize>,
+    shadowed: &HashSet<Symbol>,
+    code: &mut Vec<Instruction>,
+) {
+    match token {
+        Token::Var(name) if shadowed.contains(name) => {
+            code.push(Instruction::PutConstant(token.clone()))
+        }
+        Token::Var(name) => match registers.get(name) {
+            Some(register) => code.push(Instruction::PutValue(*register)),
+            None => code.push(Instruction::PutUnbound(*name)),
+        },
+        Token::Lst(lst) if !has_variables(token, shadowed) => {
+            code.push(Instruction::PutConstant(Token::Lst(lst.clone())))
+        }
+        Token::Lst(lst) => {
+            match &**lst {
+                [Token::Keyword(sym::QUOTE), _] => {
+                    code.push(Instruction::PutConstant(token.clone()));
+                    return;
+                }
+                [Token::Keyword(sym::QUASIQUOTE), template] => {
+                    code.push(Instruction::PutConstant(lst[0].clone()));
+                    compile_template(template, registers, shadowed, code);
+                    code.push(Instruction::PutList(2));
+                    return;
+                }
+                _ => {}
+            }
+            if let [Token::Keyword(sym::CATCH), pattern, handler] = &**lst {
+                let mut shadowed = shadowed.clone();
+                shadowed.extend(variable_names(pattern));
+
+                code.push(Instruction::PutConstant(lst[0].clone()));
+                code.push(Instruction::PutConstant(pattern.clone()));
+                compile_body(handler, registers, &shadowed, code);
+                code.push(Instruction::PutList(3));
+                return;
+            }
+            if let [Token::Keyword(sym::MATCH_CASE), expr, arms @ ..] = &**lst {
+                code.push(Instruction::PutConstant(lst[0].clone()));
+                compile_body(expr, registers, shadowed, code);
+                for arm in arms {
+                    match arm {
+                        Token::Lst(case) if case.len() == 2 => {
+                            let mut shadowed = shadowed.clone();
+                            shadowed.extend(variable_names(&case[0]));
+
+                            code.push(Instruction::PutConstant(case[0].clone()));
+                            compile_body(&case[1], registers, &shadowed, code);
+                            code.push(Instruction::PutList(2));
+                        }
+                        arm => compile_body(arm, registers, shadowed, code),
+                    }
+                }
+                code.push(Instruction::PutList(lst.len()));
+                return;
+            }
+
+            for item in lst.iter() {
+                compile_body(item, registers, shadowed, code);
+            }
+            code.push(Instruction::PutList(lst.len()));
+        }
+        atom => code.push(Instruction::PutConstant(atom.clone())),
+    }
+}
+
+// as replace_unquoted, only the forms unquoted in a quasiquoted template
+// are built from registers
+fn compile_template(
+    token: &Token,
+    registers: &HashMap<Symbol, usize>,
+    shadowed: &HashSet<Symbol>,
+    code: &mut Vec<Instruction>,
+) {
+    match token {
+        Token::Lst(lst) if matches!(&**lst, [Token::Keyword(sym::UNQUOTE | sym::SPLICE), _]) => {
+            compile_body(token, registers, shadowed, code)
+        }
+        Token::Lst(lst) => {
+            for item in lst.iter() {
+                compile_template(item, registers, shadowed, code);
+            }
+            code.push(Instruction::PutList(lst.len()));
+        }
+        atom => code.push(Instruction::PutConstant(atom.clone())),
+    }
+}
+
+// whether building token needs a register or may raise an unbound variable
+fn has_variables(token: &Token, shadowed: &HashSet<Symbol>) -> bool {
+    match token {
+        Token::Var(name) => !shadowed.contains(name),
+        Token::Lst(lst) => lst.iter().any(|item| has_variables(item, shadowed)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{Engine, EngineOptions};
+    use crate::evaluator::definition::def::{match_variable, replace_variable};
+    use crate::evaluator::match_token;
+    use crate::symbol::Interner;
+
+    fn run(program: &str, compile: bool) -> String {
+        let mut engine = Engine::with_options(EngineOptions {
+            compile,
+            ..EngineOptions::default()
+        })
+        .unwrap();
+        match engine.eval_str(program) {
+            Ok(token) => engine.render(&token),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    fn assert_same(program: &str) {
+        assert_eq!(run(program, false), run(program, true), "{}", program);
+    }
+
+    #[test]
+    fn facts_and_queries() {
+        assert_same(
+            "(.dec (likes alice tea))
+             (.dec (likes bob coffee))
+             (likes alice tea)
+             (likes alice coffee)
+             (likes bob coffee)",
+        );
+    }
+
+    #[test]
+    fn first_clause_wins() {
+        assert_same(
+            "(.dec (buy apple))
+             (.def (buy X) false)
+             (.def (checkout X) (buy X))
+             (checkout apple)
+             (checkout grape)
+             (checkout Y)",
+        );
+    }
+
+    #[test]
+    fn arithmetic_and_recursion() {
+        assert_same(
+            "(.def (fib 0) 0)
+             (.def (fib 1) 1)
+             (.def (fib N) (+ (fib (- N 2)) (fib (- N 1))))
+             (fib 12)",
+        );
+        assert_same(
+            "(.def (count 0 Acc) Acc)
+             (.def (count N Acc) (count (- N 1) (+ Acc 1)))
+             (count 2000 0)",
+        );
+    }
+
+    #[test]
+    fn nested_lists_and_wildcards() {
+        assert_same(
+            "(.def (first (X _rest)) X)
+             (.def (swap (pair A B)) (pair B A))
+             (.def (deep (a (b (c X)))) (found X (X X)))
+             (first (1 2))
+             (swap (pair 1 (x y)))
+             (deep (a (b (c 7))))
+             (deep (a (b (d 7))))",
+        );
+    }
+
+    #[test]
+    fn repeated_variables_keep_the_last_binding() {
+        assert_same(
+            "(.def (same X X) X)
+             (same 1 2)
+             (same 3 3)",
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_same("(.def (oops X) (f Y)) (oops 1)");
+        assert_same("(.def (div X) (/ X 0)) (div 1)");
+        assert_same("(.def (f 1) 1) (.def (g X) (f X X)) (g 1)");
+        assert_same("(.def (f 1) 1) (.def (loop N) (+ 1 (loop N))) (loop 1)");
+    }
+
+    #[test]
+    fn catch_patterns_shadow_head_variables() {
+        assert_same(
+            "(.def (safe X E)
+                (.try (/ X 0)
+                    (.catch (.error E Msg) (caught E))))
+             (safe 1 kind)",
+        );
+        assert_same(
+            "(.def (rethrow X)
+                (.try (.throw (boom X))
+                    (.catch (boom Y) (got X Y))))
+             (rethrow 5)",
+        );
+    }
+
+    #[test]
+    fn match_case_arms_bind_their_own_variables() {
+        let program = "(.def (area Shape X)
+                (.match-case Shape
+                    ((square X) (* X X))
+                    ((rect W H) (* W H))
+                    (_ X)))
+             (area (square 3) 0)
+             (area (rect 2 5) 0)
+             (area circle 7)";
+        assert_eq!(run(program, false), "(true 9 10 7)");
+        assert_same(program);
+        assert_same(
+            "(.def (side S) (.match-case S ((square X) X)))
+             (side (circle 1))",
+        );
+    }
+
+    #[test]
+    fn quoted_variables_are_data() {
+        let program = "(.def (keep X) '(box X))
+             (.def (rule Name N) `(.def (,Name X) (+ X ,N)))
+             (.def (sum Xs) `(+ ,@Xs))
+             (keep 1)
+             (.eval (rule add 2))
+             (add 5)
+             (.eval (sum (.quote (3 4))))";
+        assert_eq!(run(program, false), "(true true true (box X) true 7 7)");
+        assert_same(program);
+    }
+
+    #[test]
+    fn tabled_definitions() {
+        assert_same(
+            "(.table (path X Y))
+             (.dec (edge a b))
+             (.dec (edge b c))
+             (.def (path X Y) (edge X Y))
+             (path a b)
+             (path b c)
+             (path a c)",
+        );
+    }
+
+    // a small generator of terms, so the vm is compared with match_token and
+    // match_variable on many heads and inputs
+    struct Terms {
+        seed: u64,
+        symbols: Interner,
+    }
+
+    impl Terms {
+        fn next(&mut self, n: u64) -> u64 {
+            self.seed = self.seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+            (self.seed >> 33) % n
+        }
+
+        fn term(&mut self, depth: usize, variables: bool) -> Token {
+            let choice = if depth == 0 {
+                self.next(5)
+            } else {
+                self.next(7)
+            };
+            match choice {
+                0 => {
+                    let name = ["a", "b"][self.next(2) as usize];
+                    self.symbols.str(name)
+                }
+                1 => Token::Number(self.next(3) as f32),
+                2 => Token::Bool(self.next(2) == 0),
+                3 if variables => {
+                    let name = ["X", "Y", "Z"][self.next(3) as usize];
+                    self.symbols.var(name)
+                }
+                4 if variables => Token::Wildcard(self.symbols.intern("_")),
+                3 | 4 => self.symbols.keyword(".k"),
+                _ => {
+                    let len = self.next(3) as usize + 1;
+                    Token::list((0..len).map(|_| self.term(depth - 1, variables)).collect())
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn heads_match_like_the_tree_walker() {
+        let mut terms = Terms {
+            seed: 7,
+            symbols: Interner::new(),
+        };
+
+        for _ in 0..20_000 {
+            let head = terms.term(3, true);
+            let input = terms.term(3, false);
+            let body = terms.term(3, true);
+            let clause = Clause::compile(&head, &body);
+
+            let registers = clause.match_head(&input);
+            assert_eq!(registers.is_some(), match_token(&head, &input));
+
+            if let Some(registers) = registers {
+                let variables = match_variable(&head, &input);
+                let expected = replace_variable(&body, &variables);
+                assert_eq!(clause.build_body(&registers), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn ground_bodies_are_a_single_constant() {
+        let symbols = Interner::new();
+        let body = Token::list(vec![symbols.str("a"), Token::Number(1.0)]);
+        let clause = Clause::compile(&symbols.var("X"), &body);
+        assert_eq!(clause.body, vec![Instruction::PutConstant(body)]);
+    }
+}
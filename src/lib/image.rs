@@ -0,0 +1,617 @@
+use crate::error::ImageError;
+use crate::evaluator::{HeadLayout, Key, Layout, LengthLayout};
+use crate::symbol::{Interner, Symbol};
+use crate::token::Token;
+
+use std::collections::HashMap;
+
+// an image holds the clauses of a knowledge base so it can be loaded without
+// parsing or evaluating its source again:
+//
+//   magic     b"LXI\0"
+//   version   u32
+//   checksum  u32, FNV-1a of the payload
+//   length    u64, of the payload
+//   payload   symbols, tabled patterns, clauses, index, private names,
+//             macros
+//
+// numbers are little endian. symbols are stored once as names and referred to
+// by their position, so they are interned again by the engine loading the
+// image, or hidden again when no source could name them. clauses are stored
+// in the order they are tried, and the index as its buckets of clause
+// positions, so loading doesn't look each clause up again
+const MAGIC: &[u8; 4] = b"LXI\0";
+pub const VERSION: u32 = 4;
+
+// how deeply lists may nest, which no clause comes near but keeps a corrupt
+// image from building a token too deep to drop. nothing nested deeper is
+// written, so every image written can be read back
+pub const MAX_NESTING: usize = 1024;
+
+const TAG_LIST: u8 = 0;
+const TAG_VAR: u8 = 1;
+const TAG_STR: u8 = 2;
+const TAG_KEYWORD: u8 = 3;
+const TAG_NUMBER: u8 = 4;
+const TAG_BOOL: u8 = 5;
+const TAG_BINARY_OP: u8 = 6;
+const TAG_UNARY_OP: u8 = 7;
+const TAG_WILDCARD: u8 = 8;
+const TAG_UNKNOWN: u8 = 9;
+
+const KEY_ATOM: u8 = 0;
+const KEY_LIST: u8 = 1;
+
+// what an image holds, with tokens using the symbols of an engine
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Image {
+    pub tabled: Vec<Token>,
+    pub clauses: Vec<(Token, Token)>,
+    pub index: Layout,
+    // the qualified names of the private definitions among the clauses
+    pub private: Vec<Token>,
+    // the signatures and templates of macros, in the order they were defined
+    pub macros: Vec<(Token, Token)>,
+}
+
+pub fn encode(image: &Image, symbols: &Interner) -> Result<Vec<u8>, ImageError> {
+    let mut writer = Writer::default();
+    let mut tokens = vec![];
+
+    write_u32(&mut tokens, image.tabled.len() as u32);
+    for pattern in &image.tabled {
+        writer.token(&mut tokens, pattern)?;
+    }
+
+    write_u32(&mut tokens, image.clauses.len() as u32);
+    for (head, body) in &image.clauses {
+        writer.token(&mut tokens, head)?;
+        writer.token(&mut tokens, body)?;
+    }
+    writer.layout(&mut tokens, &image.index);
+    write_u32(&mut tokens, image.private.len() as u32);
+    for name in &image.private {
+        writer.token(&mut tokens, name)?;
+    }
+    write_u32(&mut tokens, image.macros.len() as u32);
+    for (signature, template) in &image.macros {
+        writer.token(&mut tokens, signature)?;
+        writer.token(&mut tokens, template)?;
+    }
+
+    let payload = writer.finish(&tokens, symbols);
+
+    let mut bytes = Vec::with_capacity(payload.len() + 20);
+    bytes.extend_from_slice(MAGIC);
+    write_u32(&mut bytes, VERSION);
+    write_u32(&mut bytes, checksum(&payload));
+    bytes.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&payload);
+    Ok(bytes)
+}
+
+pub fn decode(bytes: &[u8], symbols: &Interner) -> Result<Image, ImageError> {
+    if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(ImageError::NotAnImage);
+    }
+
+    let mut header = Reader::new(&bytes[MAGIC.len()..]);
+    let version = header.u32()?;
+    if version != VERSION {
+        return Err(ImageError::UnsupportedVersion {
+            found: version,
+            expected: VERSION,
+        });
+    }
+    let sum = header.u32()?;
+    let length = header.u64()?;
+
+    let payload = header.rest();
+    if payload.len() as u64 != length {
+        return Err(ImageError::Truncated);
+    }
+    if checksum(payload) != sum {
+        return Err(ImageError::ChecksumMismatch);
+    }
+
+    let mut reader = Reader::new(payload);
+    reader.names(symbols)?;
+
+    let mut image = Image::default();
+    for _ in 0..reader.u32()? {
+        image.tabled.push(reader.token()?);
+    }
+    for _ in 0..reader.u32()? {
+        let head = reader.token()?;
+        let body = reader.token()?;
+        image.clauses.push((head, body));
+    }
+    image.index = reader.layout()?;
+    if !image.index.is_valid(image.clauses.len()) {
+        return Err(ImageError::InvalidIndex);
+    }
+    for _ in 0..reader.u32()? {
+        image.private.push(reader.token()?);
+    }
+    for _ in 0..reader.u32()? {
+        let signature = reader.token()?;
+        let template = reader.token()?;
+        image.macros.push((signature, template));
+    }
+
+    Ok(image)
+}
+
+// tokens along with the names of their symbols, so they can be read back on
+// their own, as the records of a database log are
+pub fn encode_tokens(tokens: &[Token], symbols: &Interner) -> Result<Vec<u8>, ImageError> {
+    let mut writer = Writer::default();
+    let mut bytes = vec![];
+
+    write_u32(&mut bytes, tokens.len() as u32);
+    for token in tokens {
+        writer.token(&mut bytes, token)?;
+    }
+
+    Ok(writer.finish(&bytes, symbols))
+}
+
+// whether token nests too deeply to be read back from an image or a log
+pub fn too_deep(token: &Token) -> bool {
+    let mut pending = vec![(token, 0)];
+    while let Some((token, depth)) = pending.pop() {
+        if let Token::Lst(lst) = token {
+            if depth == MAX_NESTING && !lst.is_empty() {
+                return true;
+            }
+            pending.extend(lst.iter().map(|item| (item, depth + 1)));
+        }
+    }
+    false
+}
+
+pub fn decode_tokens(bytes: &[u8], symbols: &Interner) -> Result<Vec<Token>, ImageError> {
+    let mut reader = Reader::new(bytes);
+    reader.names(symbols)?;
+
+    let mut tokens = vec![];
+    for _ in 0..reader.u32()? {
+        tokens.push(reader.token()?);
+    }
+
+    Ok(tokens)
+}
+
+pub fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+fn write_u32(bytes: &mut Vec<u8>, n: u32) {
+    bytes.extend_from_slice(&n.to_le_bytes());
+}
+
+fn write_ids(bytes: &mut Vec<u8>, ids: &[usize]) {
+    write_u32(bytes, ids.len() as u32);
+    for id in ids {
+        write_u32(bytes, *id as u32);
+    }
+}
+
+// numbers the symbols of the tokens written, in the order they are met
+#[derive(Default)]
+struct Writer {
+    symbols: Vec<Symbol>,
+    ids: HashMap<Symbol, u32>,
+}
+
+impl Writer {
+    fn symbol(&mut self, bytes: &mut Vec<u8>, symbol: Symbol) {
+        let next = self.symbols.len() as u32;
+        let id = *self.ids.entry(symbol).or_insert(next);
+        if id == next {
+            self.symbols.push(symbol);
+        }
+        write_u32(bytes, id);
+    }
+
+    // the names of the symbols met, each followed by whether it was hidden,
+    // which go first so they are interned before any token is read, then
+    // the tokens written
+    fn finish(self, tokens: &[u8], symbols: &Interner) -> Vec<u8> {
+        let mut bytes = vec![];
+        write_u32(&mut bytes, self.symbols.len() as u32);
+        for symbol in self.symbols {
+            let name = symbols.resolve(symbol);
+            write_u32(&mut bytes, name.len() as u32);
+            bytes.extend_from_slice(name.as_bytes());
+            bytes.push(!symbols.is_interned(symbol) as u8);
+        }
+        bytes.extend_from_slice(tokens);
+        bytes
+    }
+
+    fn token(&mut self, bytes: &mut Vec<u8>, token: &Token) -> Result<(), ImageError> {
+        if too_deep(token) {
+            return Err(ImageError::TooDeep { limit: MAX_NESTING });
+        }
+
+        self.nested(bytes, token);
+        Ok(())
+    }
+
+    fn nested(&mut self, bytes: &mut Vec<u8>, token: &Token) {
+        match token {
+            Token::Lst(lst) => {
+                bytes.push(TAG_LIST);
+                write_u32(bytes, lst.len() as u32);
+                for item in lst.iter() {
+                    self.nested(bytes, item);
+                }
+            }
+            Token::Var(s) => {
+                bytes.push(TAG_VAR);
+                self.symbol(bytes, *s);
+            }
+            Token::Str(s) => {
+                bytes.push(TAG_STR);
+                self.symbol(bytes, *s);
+            }
+            Token::Keyword(s) => {
+                bytes.push(TAG_KEYWORD);
+                self.symbol(bytes, *s);
+            }
+            Token::Number(num) => {
+                bytes.push(TAG_NUMBER);
+                bytes.extend_from_slice(&num.to_le_bytes());
+            }
+            Token::Bool(b) => {
+                bytes.push(TAG_BOOL);
+                bytes.push(*b as u8);
+            }
+            Token::BinaryOp(s) => {
+                bytes.push(TAG_BINARY_OP);
+                self.symbol(bytes, *s);
+            }
+            Token::UnaryOp(s) => {
+                bytes.push(TAG_UNARY_OP);
+                self.symbol(bytes, *s);
+            }
+            Token::Wildcard(s) => {
+                bytes.push(TAG_WILDCARD);
+                self.symbol(bytes, *s);
+            }
+            Token::Unknown => bytes.push(TAG_UNKNOWN),
+        }
+    }
+
+    fn key(&mut self, bytes: &mut Vec<u8>, key: &Key) {
+        match key {
+            Key::Atom(token) => {
+                bytes.push(KEY_ATOM);
+                self.nested(bytes, token);
+            }
+            Key::List(len) => {
+                bytes.push(KEY_LIST);
+                write_u32(bytes, *len as u32);
+            }
+        }
+    }
+
+    fn layout(&mut self, bytes: &mut Vec<u8>, layout: &Layout) {
+        write_ids(bytes, &layout.others);
+        write_u32(bytes, layout.lengths.len() as u32);
+        for length in &layout.lengths {
+            write_u32(bytes, length.length as u32);
+            write_ids(bytes, &length.any_head);
+            write_u32(bytes, length.heads.len() as u32);
+            for head in &length.heads {
+                self.key(bytes, &head.head);
+                write_ids(bytes, &head.any_first);
+                write_u32(bytes, head.by_first.len() as u32);
+                for (first, ids) in &head.by_first {
+                    self.key(bytes, first);
+                    write_ids(bytes, ids);
+                }
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    // the symbols of the image, interned by the engine loading it
+    symbols: Vec<Symbol>,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Reader {
+            bytes,
+            pos: 0,
+            symbols: vec![],
+        }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], ImageError> {
+        let end = self.pos.checked_add(len).ok_or(ImageError::Truncated)?;
+        let bytes = self.bytes.get(self.pos..end).ok_or(ImageError::Truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.bytes[self.pos..];
+        self.pos = self.bytes.len();
+        rest
+    }
+
+    fn u8(&mut self) -> Result<u8, ImageError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, ImageError> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn u64(&mut self) -> Result<u64, ImageError> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn names(&mut self, symbols: &Interner) -> Result<(), ImageError> {
+        for _ in 0..self.u32()? {
+            let len = self.u32()? as usize;
+            let name =
+                std::str::from_utf8(self.bytes(len)?).map_err(|_| ImageError::InvalidName)?;
+            let symbol = match self.u8()? {
+                0 => symbols.intern(name),
+                _ => symbols.hidden(name),
+            };
+            self.symbols.push(symbol);
+        }
+        Ok(())
+    }
+
+    fn symbol(&mut self) -> Result<Symbol, ImageError> {
+        let id = self.u32()?;
+        self.symbols
+            .get(id as usize)
+            .copied()
+            .ok_or(ImageError::InvalidSymbol(id))
+    }
+
+    // reads lists with a stack of their items so far rather than recursing,
+    // so how deeply they nest is only bounded by MAX_NESTING
+    fn token(&mut self) -> Result<Token, ImageError> {
+        let mut open: Vec<(Vec<Token>, usize)> = vec![];
+        loop {
+            let mut token = match self.u8()? {
+                TAG_LIST => {
+                    let len = self.u32()? as usize;
+                    if len > 0 {
+                        if open.len() == MAX_NESTING {
+                            return Err(ImageError::TooDeep { limit: MAX_NESTING });
+                        }
+                        // every item takes at least a byte, which keeps a
+                        // corrupt length from reserving more than the image
+                        // holds
+                        let items = Vec::with_capacity(len.min(self.bytes.len() - self.pos));
+                        open.push((items, len));
+                        continue;
+                    }
+                    Token::list(vec![])
+                }
+                TAG_VAR => Token::Var(self.symbol()?),
+                TAG_STR => Token::Str(self.symbol()?),
+                TAG_KEYWORD => Token::Keyword(self.symbol()?),
+                TAG_NUMBER => {
+                    let mut buf = [0; 4];
+                    buf.copy_from_slice(self.bytes(4)?);
+                    Token::Number(f32::from_le_bytes(buf))
+                }
+                TAG_BOOL => Token::Bool(self.u8()? != 0),
+                TAG_BINARY_OP => Token::BinaryOp(self.symbol()?),
+                TAG_UNARY_OP => Token::UnaryOp(self.symbol()?),
+                TAG_WILDCARD => Token::Wildcard(self.symbol()?),
+                TAG_UNKNOWN => Token::Unknown,
+                tag => return Err(ImageError::InvalidTag(tag)),
+            };
+
+            // the token goes in the innermost open list, closing the lists
+            // it completes
+            loop {
+                let (items, len) = match open.last_mut() {
+                    Some(list) => list,
+                    None => return Ok(token),
+                };
+                items.push(token);
+                if items.len() < *len {
+                    break;
+                }
+                let (items, _) = open.pop().unwrap();
+                token = Token::list(items);
+            }
+        }
+    }
+
+    fn key(&mut self) -> Result<Key, ImageError> {
+        match self.u8()? {
+            KEY_ATOM => Ok(Key::Atom(self.token()?)),
+            KEY_LIST => Ok(Key::List(self.u32()? as usize)),
+            tag => Err(ImageError::InvalidTag(tag)),
+        }
+    }
+
+    fn ids(&mut self) -> Result<Vec<usize>, ImageError> {
+        let len = self.u32()? as usize;
+        let mut ids = Vec::with_capacity(len.min(self.bytes.len() - self.pos));
+        for _ in 0..len {
+            ids.push(self.u32()? as usize);
+        }
+        Ok(ids)
+    }
+
+    fn layout(&mut self) -> Result<Layout, ImageError> {
+        let mut layout = Layout {
+            others: self.ids()?,
+            lengths: vec![],
+        };
+        for _ in 0..self.u32()? {
+            let mut length = LengthLayout {
+                length: self.u32()? as usize,
+                any_head: self.ids()?,
+                heads: vec![],
+            };
+            for _ in 0..self.u32()? {
+                let mut head = HeadLayout {
+                    head: self.key()?,
+                    any_first: self.ids()?,
+                    by_first: vec![],
+                };
+                for _ in 0..self.u32()? {
+                    let first = self.key()?;
+                    head.by_first.push((first, self.ids()?));
+                }
+                length.heads.push(head);
+            }
+            layout.lengths.push(length);
+        }
+        Ok(layout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(symbols: &Interner) -> Image {
+        let head = Token::list(vec![
+            symbols.str("edge"),
+            symbols.var("X"),
+            Token::Number(1.5),
+        ]);
+        let body = Token::list(vec![
+            symbols.binary_op("+"),
+            Token::Bool(false),
+            Token::Wildcard(symbols.intern("_y")),
+        ]);
+        let index = Layout {
+            others: vec![1],
+            lengths: vec![LengthLayout {
+                length: 3,
+                any_head: vec![],
+                heads: vec![HeadLayout {
+                    head: Key::Atom(symbols.str("edge")),
+                    any_first: vec![0],
+                    by_first: vec![],
+                }],
+            }],
+        };
+        Image {
+            tabled: vec![Token::list(vec![symbols.str("path"), symbols.var("X")])],
+            clauses: vec![
+                (head, body),
+                (Token::Str(symbols.hidden("m/helper")), Token::Bool(true)),
+            ],
+            index,
+            private: vec![symbols.str("m/helper")],
+            macros: vec![(
+                Token::list(vec![symbols.str("twice"), symbols.var("X")]),
+                Token::list(vec![
+                    symbols.binary_op("*"),
+                    symbols.var("X"),
+                    Token::Number(2.0),
+                ]),
+            )],
+        }
+    }
+
+    #[test]
+    fn round_trip_through_another_interner() {
+        let symbols = Interner::new();
+        let bytes = encode(&image(&symbols), &symbols).unwrap();
+
+        // a fresh interner gives out other ids for the same names
+        let other = Interner::new();
+        other.intern("unrelated");
+        assert_eq!(decode(&bytes, &other), Ok(image(&other)));
+    }
+
+    #[test]
+    fn rejects_bad_images() {
+        let symbols = Interner::new();
+        let bytes = encode(&image(&symbols), &symbols).unwrap();
+
+        assert_eq!(decode(b"(.dec a)", &symbols), Err(ImageError::NotAnImage));
+
+        let mut stale = bytes.clone();
+        stale[4] = 0;
+        assert_eq!(
+            decode(&stale, &symbols),
+            Err(ImageError::UnsupportedVersion {
+                found: 0,
+                expected: VERSION
+            })
+        );
+
+        let mut corrupt = bytes.clone();
+        *corrupt.last_mut().unwrap() ^= 1;
+        assert_eq!(
+            decode(&corrupt, &symbols),
+            Err(ImageError::ChecksumMismatch)
+        );
+
+        assert_eq!(
+            decode(&bytes[..bytes.len() - 1], &symbols),
+            Err(ImageError::Truncated)
+        );
+    }
+
+    #[test]
+    fn rejects_an_index_not_holding_each_clause_once() {
+        let symbols = Interner::new();
+        let mut image = image(&symbols);
+        image.index.others = vec![0, 1];
+
+        let bytes = encode(&image, &symbols).unwrap();
+        assert_eq!(decode(&bytes, &symbols), Err(ImageError::InvalidIndex));
+    }
+
+    #[test]
+    fn lists_nest_up_to_a_limit() {
+        let symbols = Interner::new();
+        let nested =
+            |depth: usize| (0..depth).fold(Token::Bool(true), |token, _| Token::list(vec![token]));
+
+        let bytes = encode_tokens(&[nested(MAX_NESTING)], &symbols).unwrap();
+        assert_eq!(
+            decode_tokens(&bytes, &symbols),
+            Ok(vec![nested(MAX_NESTING)])
+        );
+
+        assert_eq!(
+            encode_tokens(&[nested(MAX_NESTING + 1)], &symbols),
+            Err(ImageError::TooDeep { limit: MAX_NESTING })
+        );
+
+        // nor is one nested deeper read, should it be written some other way
+        let mut bytes = vec![];
+        write_u32(&mut bytes, 0);
+        write_u32(&mut bytes, 1);
+        for _ in 0..=MAX_NESTING {
+            bytes.push(TAG_LIST);
+            write_u32(&mut bytes, 1);
+        }
+        bytes.extend_from_slice(&[TAG_BOOL, 1]);
+        assert_eq!(
+            decode_tokens(&bytes, &symbols),
+            Err(ImageError::TooDeep { limit: MAX_NESTING })
+        );
+    }
+}
@@ -0,0 +1,318 @@
+use crate::engine::io_error;
+use crate::error::{LexError, ManifestError};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use toml::value::{Table, Value};
+
+// a package is a directory with a Lex.toml naming it, the file run for it and
+// the packages it imports from:
+//
+//   [package]
+//   name = "family"
+//   entry = "src/main.lx"
+//
+//   [dependencies]
+//   shop = { path = "../shop" }
+//   rules = { vendored = true }
+//
+// a dependency is a package found at path, or in vendor/<name> when vendored,
+// and is imported by the name it is given here, as in (.import shop). the
+// file is read as toml, so [dependencies.shop] can give shop a table of its
+// own
+pub const FILE: &str = "Lex.toml";
+const VENDOR: &str = "vendor";
+const DEFAULT_ENTRY: &str = "main.lx";
+
+pub struct Manifest {
+    pub name: String,
+    pub entry: PathBuf,
+    pub dependencies: Vec<(String, Dependency)>,
+}
+
+pub enum Dependency {
+    Path(PathBuf),
+    Vendored,
+}
+
+// a package resolved to the absolute path of its entry file
+pub struct Package {
+    pub name: String,
+    pub entry: PathBuf,
+}
+
+impl Manifest {
+    pub fn load(dir: &Path) -> Result<Manifest, LexError> {
+        let path = dir.join(FILE);
+        let source = std::fs::read_to_string(&path).map_err(|err| io_error(&path, err))?;
+        Manifest::parse(&source).map_err(|err| LexError::Manifest(path.display().to_string(), err))
+    }
+
+    fn parse(source: &str) -> Result<Manifest, ManifestError> {
+        let root: Table = toml::from_str(source).map_err(|err| {
+            // the line is given apart, rather than at the end of the reason
+            let reason = err.to_string();
+            ManifestError::Syntax {
+                line: err.line_col().map_or(0, |(line, _)| line + 1),
+                reason: match reason.split_once(" at line ") {
+                    Some((reason, _)) => reason.to_owned(),
+                    None => reason,
+                },
+            }
+        })?;
+
+        let mut name = None;
+        let mut entry = None;
+        let mut dependencies = vec![];
+        for (table, value) in root {
+            let fields = match value {
+                Value::Table(fields) => fields,
+                _ => return Err(ManifestError::UnknownKey(table)),
+            };
+            for (key, value) in fields {
+                match (table.as_str(), key.as_str(), value) {
+                    ("package", "name", Value::String(value)) => name = Some(value),
+                    ("package", "entry", Value::String(value)) => {
+                        entry = Some(PathBuf::from(value))
+                    }
+                    ("package", "version" | "description", Value::String(_)) => {}
+                    ("package", "name" | "entry" | "version" | "description", _) => {
+                        return Err(invalid_value(&table, &key, "a string"))
+                    }
+                    ("dependencies", name, Value::Table(fields)) => {
+                        dependencies.push((name.to_owned(), dependency(name, fields)?))
+                    }
+                    ("dependencies", _, _) => return Err(invalid_value(&table, &key, "a table")),
+                    (table, key, _) => {
+                        return Err(ManifestError::UnknownKey(format!("{}.{}", table, key)))
+                    }
+                }
+            }
+        }
+
+        Ok(Manifest {
+            name: name.ok_or_else(|| ManifestError::MissingKey("package.name".to_owned()))?,
+            entry: entry.unwrap_or_else(|| PathBuf::from(DEFAULT_ENTRY)),
+            dependencies,
+        })
+    }
+}
+
+fn invalid_value(table: &str, key: &str, expected: &str) -> ManifestError {
+    ManifestError::InvalidValue {
+        key: format!("{}.{}", table, key),
+        expected: expected.to_owned(),
+    }
+}
+
+fn dependency(name: &str, fields: Table) -> Result<Dependency, ManifestError> {
+    let table = format!("dependencies.{}", name);
+    let mut dependency = None;
+    for (key, value) in fields {
+        dependency = match (key.as_str(), value) {
+            ("path", Value::String(path)) => Some(Dependency::Path(PathBuf::from(path))),
+            ("vendored", Value::Boolean(true)) => Some(Dependency::Vendored),
+            ("vendored", Value::Boolean(false)) => dependency,
+            ("path", _) => return Err(invalid_value(&table, &key, "a string")),
+            ("vendored", _) => return Err(invalid_value(&table, &key, "a boolean")),
+            (key, _) => return Err(ManifestError::UnknownKey(format!("{}.{}", table, key))),
+        };
+    }
+
+    dependency.ok_or_else(|| ManifestError::MissingKey(format!("{}.path", table)))
+}
+
+// the package in dir and every package it depends on, directly or not
+pub fn resolve(dir: &Path) -> Result<(Package, Vec<Package>), LexError> {
+    let mut resolved = HashMap::new();
+    let mut dependencies = vec![];
+    let manifest = Manifest::load(dir)?;
+    let root = Package {
+        name: manifest.name.clone(),
+        entry: canonical(&dir.join(&manifest.entry))?,
+    };
+
+    let mut stack = vec![(manifest.name.clone(), canonical(dir)?)];
+    resolve_dependencies(dir, &manifest, &mut stack, &mut resolved, &mut dependencies)?;
+    Ok((root, dependencies))
+}
+
+fn resolve_dependencies(
+    dir: &Path,
+    manifest: &Manifest,
+    stack: &mut Vec<(String, PathBuf)>,
+    resolved: &mut HashMap<String, PathBuf>,
+    dependencies: &mut Vec<Package>,
+) -> Result<(), LexError> {
+    let invalid = |err| LexError::Manifest(dir.join(FILE).display().to_string(), err);
+
+    for (name, dependency) in &manifest.dependencies {
+        let path = match dependency {
+            Dependency::Path(path) => dir.join(path),
+            Dependency::Vendored => dir.join(VENDOR).join(name),
+        };
+        let path = canonical(&path)?;
+
+        if let Some(at) = stack.iter().position(|(_, other)| *other == path) {
+            let mut cycle: Vec<String> = stack[at..].iter().map(|(name, _)| name.clone()).collect();
+            cycle.push(name.clone());
+            return Err(invalid(ManifestError::DependencyCycle(cycle)));
+        }
+        match resolved.get(name) {
+            Some(other) if *other == path => continue,
+            Some(other) => {
+                return Err(invalid(ManifestError::ConflictingDependency {
+                    name: name.clone(),
+                    first: other.display().to_string(),
+                    second: path.display().to_string(),
+                }))
+            }
+            None => {}
+        }
+
+        let manifest = Manifest::load(&path)?;
+        stack.push((name.clone(), path.clone()));
+        resolve_dependencies(&path, &manifest, stack, resolved, dependencies)?;
+        stack.pop();
+
+        resolved.insert(name.clone(), path.clone());
+        dependencies.push(Package {
+            name: name.clone(),
+            entry: canonical(&path.join(&manifest.entry))?,
+        });
+    }
+
+    Ok(())
+}
+
+fn canonical(path: &Path) -> Result<PathBuf, LexError> {
+    path.canonicalize().map_err(|err| io_error(path, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::testing::{eval, TempDir};
+
+    fn paths(manifest: &Manifest) -> Vec<(&str, Option<&Path>)> {
+        manifest
+            .dependencies
+            .iter()
+            .map(|(name, dependency)| match dependency {
+                Dependency::Path(path) => (name.as_str(), Some(path.as_path())),
+                Dependency::Vendored => (name.as_str(), None),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reads_any_way_toml_writes_it() {
+        let manifest = Manifest::parse(
+            r#"
+            [package]
+            name = "fam\"ily" # a comment
+            entry = 'src\main.lx'
+
+            [dependencies]
+            rules = { vendored = true }
+
+            [dependencies.shop]
+            path = "../shop#1"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(manifest.name, "fam\"ily");
+        assert_eq!(manifest.entry, PathBuf::from("src\\main.lx"));
+        assert_eq!(
+            paths(&manifest),
+            [("rules", None), ("shop", Some(Path::new("../shop#1")))]
+        );
+    }
+
+    #[test]
+    fn rejects_what_it_doesnt_know() {
+        let parse = |source: &str| Manifest::parse(source).err();
+
+        assert!(matches!(
+            parse("[package]\nname = \"a\"\nentry = "),
+            Some(ManifestError::Syntax { line: 3, .. })
+        ));
+        assert_eq!(
+            parse("[package]\nname = \"a\"\nauthor = \"b\""),
+            Some(ManifestError::UnknownKey("package.author".to_owned()))
+        );
+        assert_eq!(
+            parse("[package]\nname = 1"),
+            Some(ManifestError::InvalidValue {
+                key: "package.name".to_owned(),
+                expected: "a string".to_owned()
+            })
+        );
+        assert_eq!(
+            parse("[package]\nname = \"a\"\n[dependencies.shop]\nvendored = false"),
+            Some(ManifestError::MissingKey(
+                "dependencies.shop.path".to_owned()
+            ))
+        );
+        assert_eq!(
+            parse("[package]\nentry = \"a.lx\""),
+            Some(ManifestError::MissingKey("package.name".to_owned()))
+        );
+    }
+
+    #[test]
+    fn packages_import_their_dependencies_by_name() {
+        let dir = TempDir::new("package");
+        let write = |path: &str, source: &str| {
+            dir.write(path, source);
+        };
+
+        write(
+            "app/Lex.toml",
+            "[package]\nname = \"app\" # the main one\nentry = \"src/main.lx\"\n\n\
+             [dependencies]\nshop = { path = \"../shop\" }\nrules = { vendored = true }\n",
+        );
+        write(
+            "app/src/main.lx",
+            "(.import shop) (.import rules) (.set total (shop/price tea))",
+        );
+        write("app/vendor/rules/Lex.toml", "[package]\nname = \"rules\"\n");
+        write("app/vendor/rules/main.lx", "(.dec (tax 1))");
+        write(
+            "shop/Lex.toml",
+            "[package]\nname = \"shop\"\n[dependencies]\nrules = { path = \"../app/vendor/rules\" }\n",
+        );
+        write(
+            "shop/main.lx",
+            "(.import rules) (.def (price tea) (+ 2 (.get tax)))",
+        );
+
+        let mut engine = Engine::new();
+        eval(&mut engine, "(.set tax 1)");
+        engine.eval_package(dir.join("app")).unwrap();
+        assert_eq!(eval(&mut engine, "(.get total) (rules/tax 1)"), "(3 true)");
+
+        write(
+            "app/vendor/rules/Lex.toml",
+            "[package]\nname = \"rules\"\n[dependencies]\nshop = { path = \"../../../shop\" }\n",
+        );
+        let res = Engine::new()
+            .eval_package(dir.join("app"))
+            .unwrap_err()
+            .to_string();
+        assert!(
+            res.contains("dependency cycle shop -> rules -> shop"),
+            "{}",
+            res
+        );
+
+        write("app/Lex.toml", "[package]\nentry = \"src/main.lx\"\n");
+        let res = Engine::new()
+            .eval_package(dir.join("app"))
+            .unwrap_err()
+            .to_string();
+        assert!(res.ends_with("missing package.name"), "{}", res);
+    }
+}
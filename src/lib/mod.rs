@@ -1,6 +1,113 @@
-pub mod engine;
-pub mod error;
-pub mod evaluator;
-pub mod parser;
-pub mod printer;
-pub mod token;
+//! Lex is a small rewriting language: a program is a list of forms, and a
+//! form is rewritten by the first definition whose signature it matches until
+//! nothing matches anymore.
+//!
+//! An [`Engine`] parses and evaluates source, keeping the definitions it
+//! gives for later evaluations:
+//!
+//! ```
+//! use lex_lang::Engine;
+//!
+//! let mut engine = Engine::new();
+//! engine.eval_str("(.dec (likes alice tea)) (.dec (likes bob coffee))")?;
+//!
+//! let value = engine.eval_str("(likes alice tea)")?;
+//! assert_eq!(engine.render(&value), "(true)");
+//!
+//! for bindings in engine.query("(likes Who What)")? {
+//!     let who = bindings.get("Who").map(|token| engine.render(token));
+//!     println!("{:?}", who);
+//! }
+//! # Ok::<(), lex_lang::LexError>(())
+//! ```
+//!
+//! Rust functions are defined with [`Engine::register`], and whole sets of
+//! native definitions by implementing [`Module`] and loading it with
+//! [`Engine::load_module`].
+
+// lets the code generated by sig! and #[native] name this crate from inside it
+extern crate self as lex_lang;
+
+mod database;
+mod engine;
+mod error;
+mod evaluator;
+mod image;
+mod manifest;
+mod parser;
+mod printer;
+mod symbol;
+#[cfg(test)]
+mod testing;
+mod token;
+
+pub use engine::{Bindings, Engine, EngineOptions};
+pub use error::{
+    DatabaseError, EvalError, ImageError, LexError, ManifestError, ModuleError, ParseError,
+};
+pub use evaluator::budget::CancellationToken;
+pub use evaluator::definition::native::{FromToken, IntoEvalResult, IntoToken, NativeFunction};
+pub use evaluator::definition::{Definition, Handler, Module};
+pub use evaluator::trace::Backtrace;
+pub use evaluator::{EvalResult, EvalState, Snapshot};
+/// Turns a function into one giving its [`Definition`], the signature of
+/// which is the name of the function, or the name given, followed by a
+/// variable for each parameter:
+///
+/// ```
+/// use lex_lang::{native, Definition, Interner, Module};
+///
+/// // (.repeat Text Times)
+/// #[native(".repeat")]
+/// fn repeat(text: String, times: usize) -> String {
+///     text.repeat(times)
+/// }
+///
+/// struct Text;
+///
+/// impl Module for Text {
+///     fn name(&self) -> &str {
+///         "text"
+///     }
+///
+///     fn load(&self, symbols: &Interner) -> Vec<Definition> {
+///         vec![repeat(symbols)]
+///     }
+/// }
+///
+/// let mut engine = lex_lang::Engine::new();
+/// engine.load_module(Text)?;
+/// let value = engine.eval_str("(.repeat ab 3)")?;
+/// assert_eq!(engine.render(&value), "(ababab)");
+/// # Ok::<(), lex_lang::LexError>(())
+/// ```
+///
+/// Arguments and results are converted as for [`Engine::register`].
+pub use lex_macros::native;
+/// Writes a [`Token`] in Lex syntax, checked when the crate is compiled, with
+/// its symbols from the [`Interner`] given first:
+///
+/// ```
+/// use lex_lang::{sig, Interner, Token};
+///
+/// let symbols = Interner::new();
+/// let token = sig!(symbols, (.def (is-male X) true));
+/// assert_eq!(
+///     token,
+///     Token::list(vec![
+///         symbols.keyword(".def"),
+///         Token::list(vec![symbols.str("is-male"), symbols.var("X")]),
+///         Token::Bool(true),
+///     ])
+/// );
+/// ```
+///
+/// A form that isn't valid Lex fails to compile:
+///
+/// ```compile_fail
+/// let symbols = lex_lang::Interner::new();
+/// let token = lex_lang::sig!(symbols, (likes # tea));
+/// ```
+pub use lex_macros::sig;
+pub use symbol::{Interner, Symbol};
+pub use token::{Span, Token};
@@ -1,5 +1,9 @@
-use crate::lib::error::ParseError;
-use crate::lib::token::Token;
+use crate::error::ParseError;
+use crate::symbol::{sym, Interner, Symbol};
+use crate::token::{Span, Token};
+
+use std::collections::HashMap;
+use std::sync::Arc;
 
 fn is_newline(c: char) -> bool {
     c == '\n'
@@ -12,7 +16,21 @@ enum State {
     Symbol(char, i32, i32),
     LstStart(i32, i32),
     Whitespace(i32, i32),
-    Parsed(Token),
+    Parsed(Token, i32, i32),
+    // ' ` , or ,@ before a form
+    Quote(char, i32, i32),
+}
+
+fn position(state: &State) -> (i32, i32) {
+    match state {
+        State::Char(_, lin, col) => (*lin, *col),
+        State::Num(_, lin, col) => (*lin, *col),
+        State::Symbol(_, lin, col) => (*lin, *col),
+        State::LstStart(lin, col) => (*lin, *col),
+        State::Whitespace(lin, col) => (*lin, *col),
+        State::Parsed(_, lin, col) => (*lin, *col),
+        State::Quote(_, lin, col) => (*lin, *col),
+    }
 }
 
 fn parse_number(input: Vec<State>) -> Result<Token, ParseError> {
@@ -32,13 +50,9 @@ fn parse_number(input: Vec<State>) -> Result<Token, ParseError> {
         match state {
             State::Num(c, _, _) => num.push(c),
             State::Symbol(c, lin, col) => match c {
-                '.' => {
-                    if !is_float {
-                        is_float = true;
-                        num.push(c);
-                    } else {
-                        return Err(ParseError::InvalidCharacter(c, lin, col));
-                    }
+                '.' if !is_float => {
+                    is_float = true;
+                    num.push(c);
                 }
                 _ => return Err(ParseError::InvalidCharacter(c, lin, col)),
             },
@@ -47,26 +61,20 @@ fn parse_number(input: Vec<State>) -> Result<Token, ParseError> {
         };
     }
 
-    if is_float {
-        Ok(Token::Number(num.parse().unwrap()))
-    } else {
-        Ok(Token::Number(num.parse().unwrap()))
+    match num.parse() {
+        Ok(num) => Ok(Token::Number(num)),
+        Err(_) => Err(ParseError::UnknownError),
     }
 }
 
-fn parse_quoted_string(input: Vec<State>) -> Result<Token, ParseError> {
+fn parse_quoted_string(symbols: &Interner, input: Vec<State>) -> Result<Token, ParseError> {
     let mut states = input.clone();
-    match states.first() {
-        Some(State::Symbol('"', _, _)) => {
-            if states.len() > 1 {
-                if let Some(State::Symbol('"', _, _)) = states.last() {
-                    states.remove(0);
-                    states.pop();
-                }
-            }
-        }
-        _ => {}
-    };
+    if let (Some(State::Symbol('"', _, _)), Some(State::Symbol('"', _, _)), true) =
+        (states.first(), states.last(), states.len() > 1)
+    {
+        states.remove(0);
+        states.pop();
+    }
     let mut st = String::new();
     for state in states {
         match state {
@@ -77,24 +85,20 @@ fn parse_quoted_string(input: Vec<State>) -> Result<Token, ParseError> {
         };
     }
 
-    Ok(Token::Str(st))
+    Ok(Token::Str(symbols.intern(&st)))
 }
-fn parse_unquoted_string(input: Vec<State>) -> Result<Token, ParseError> {
+fn parse_unquoted_string(symbols: &Interner, input: Vec<State>) -> Result<Token, ParseError> {
     let states = input.clone();
     match states.first() {
-        Some(State::Char(c, _, _)) => {
-            match c {
-                'A'..='Z' => {
-                    return parse_variable(states);
-                }
-                _ => {}
-            };
+        Some(State::Char('A'..='Z', _, _)) => {
+            return parse_variable(symbols, states);
         }
-        Some(State::Symbol('.', _, _)) => {
-            return parse_keyword(states);
+        // :only and the like are options, read as keywords
+        Some(State::Symbol('.' | ':', _, _)) => {
+            return parse_keyword(symbols, states);
         }
         Some(State::Symbol('_', _, _)) => {
-            return parse_wildcard(states);
+            return parse_wildcard(symbols, states);
         }
         _ => {}
     };
@@ -105,7 +109,8 @@ fn parse_unquoted_string(input: Vec<State>) -> Result<Token, ParseError> {
             State::Char(c, _, _) => st.push(c),
             State::Symbol(c, lin, col) => {
                 match c {
-                    '-' | '_' => st.push(c),
+                    // a slash qualifies a name by its module, as in family/size
+                    '-' | '_' | '/' => st.push(c),
                     _ => return Err(ParseError::InvalidCharacter(c, lin, col)),
                 };
             }
@@ -119,11 +124,11 @@ fn parse_unquoted_string(input: Vec<State>) -> Result<Token, ParseError> {
     } else if st == "false" {
         Ok(Token::Bool(false))
     } else {
-        Ok(Token::Str(st))
+        Ok(Token::Str(symbols.intern(&st)))
     }
 }
 
-fn parse_wildcard(input: Vec<State>) -> Result<Token, ParseError> {
+fn parse_wildcard(symbols: &Interner, input: Vec<State>) -> Result<Token, ParseError> {
     let mut st = String::new();
 
     let mut states = input.clone();
@@ -146,15 +151,15 @@ fn parse_wildcard(input: Vec<State>) -> Result<Token, ParseError> {
         };
     }
 
-    return Ok(Token::Wildcard(st));
+    Ok(Token::Wildcard(symbols.intern(&st)))
 }
 
-fn parse_keyword(input: Vec<State>) -> Result<Token, ParseError> {
+fn parse_keyword(symbols: &Interner, input: Vec<State>) -> Result<Token, ParseError> {
     let mut st = String::new();
 
     let mut states = input.clone();
-    if let Some(State::Symbol('.', _, _)) = states.first() {
-        st.push('.');
+    if let Some(State::Symbol(c @ ('.' | ':'), _, _)) = states.first() {
+        st.push(*c);
         states.remove(0);
     }
 
@@ -163,7 +168,7 @@ fn parse_keyword(input: Vec<State>) -> Result<Token, ParseError> {
             State::Char(c, _, _) => st.push(c),
             State::Symbol(c, lin, col) => {
                 match c {
-                    '-' | '_' => st.push(c),
+                    '-' | '_' | '/' => st.push(c),
                     _ => return Err(ParseError::InvalidCharacter(c, lin, col)),
                 };
             }
@@ -172,10 +177,10 @@ fn parse_keyword(input: Vec<State>) -> Result<Token, ParseError> {
         };
     }
 
-    return Ok(Token::Keyword(st));
+    Ok(Token::Keyword(symbols.intern(&st)))
 }
 
-fn parse_variable(states: Vec<State>) -> Result<Token, ParseError> {
+fn parse_variable(symbols: &Interner, states: Vec<State>) -> Result<Token, ParseError> {
     let mut st = String::new();
     for state in states {
         match state {
@@ -191,11 +196,12 @@ fn parse_variable(states: Vec<State>) -> Result<Token, ParseError> {
         };
     }
 
-    return Ok(Token::Var(st));
+    Ok(Token::Var(symbols.intern(&st)))
 }
 
-fn parse_symbol(input: Vec<State>) -> Result<Token, ParseError> {
+fn parse_symbol(symbols: &Interner, input: Vec<State>) -> Result<Token, ParseError> {
     let mut sym = String::new();
+    let (line, collumn) = input.first().map(position).unwrap_or((0, 0));
     let states = input.clone();
     for state in states {
         match state {
@@ -208,39 +214,68 @@ fn parse_symbol(input: Vec<State>) -> Result<Token, ParseError> {
 
     match &*sym {
         "+" | "-" | "<" | ">" | "<=" | ">=" | "*" | "=" | "**" | "%" | "/" => {
-            Ok(Token::BinaryOp(sym))
+            Ok(Token::BinaryOp(symbols.intern(&sym)))
         }
-        "!" => Ok(Token::UnaryOp(sym)),
-        _ => Err(ParseError::UnknownError),
+        "!" => Ok(Token::UnaryOp(symbols.intern(&sym))),
+        _ => Err(ParseError::InvalidSymbol(sym, line, collumn)),
     }
 }
 
-fn parse_list(input: Vec<State>) -> Result<Token, ParseError> {
+// 'X is (.quote X), `X (.quasiquote X), ,X (.unquote X) and ,@X
+// (.unquote-splicing X)
+fn quote_form(c: char) -> Symbol {
+    match c {
+        '`' => sym::QUASIQUOTE,
+        ',' => sym::UNQUOTE,
+        '@' => sym::SPLICE,
+        _ => sym::QUOTE,
+    }
+}
+
+fn quoted(quotes: &mut Vec<State>, token: Token) -> Token {
+    quotes
+        .drain(..)
+        .rev()
+        .fold(token, |token, quote| match quote {
+            State::Quote(c, _, _) => Token::list(vec![Token::Keyword(quote_form(c)), token]),
+            _ => token,
+        })
+}
+
+// also returns where each element of the list starts
+fn parse_list(symbols: &Interner, input: Vec<State>) -> Result<(Token, Vec<Span>), ParseError> {
     let mut states = input.clone();
     states.push(State::Whitespace(0, 0));
 
     let mut lst: Vec<Token> = vec![];
+    let mut spans: Vec<Span> = vec![];
     let mut accumulator: Vec<State> = vec![];
+    // the quotes before the next element
+    let mut quotes: Vec<State> = vec![];
     for state in states {
         match state {
             State::Whitespace(_, _) => {
+                let (line, column) = accumulator.first().map(position).unwrap_or((0, 0));
                 let parse_result = match accumulator.first() {
-                    Some(State::Char(_, _, _)) => parse_unquoted_string(accumulator),
+                    Some(State::Char(_, _, _)) => parse_unquoted_string(symbols, accumulator),
                     Some(State::Num(_, _, _)) => parse_number(accumulator),
                     Some(State::Symbol(c, _, _)) => {
-                        if *c == '.' || *c == '_' {
-                            parse_unquoted_string(accumulator)
+                        if *c == '.' || *c == '_' || *c == ':' {
+                            parse_unquoted_string(symbols, accumulator)
                         } else if *c == '"' {
-                            parse_quoted_string(accumulator)
+                            parse_quoted_string(symbols, accumulator)
                         } else {
-                            parse_symbol(accumulator)
+                            parse_symbol(symbols, accumulator)
                         }
                     }
                     _ => Ok(Token::Unknown),
                 };
                 match parse_result {
                     Ok(Token::Unknown) => {}
-                    Ok(token) => lst.push(token),
+                    Ok(token) => {
+                        lst.push(quoted(&mut quotes, token));
+                        spans.push(Span { line, column });
+                    }
                     Err(err) => return Err(err),
                 };
                 accumulator = vec![];
@@ -248,28 +283,91 @@ fn parse_list(input: Vec<State>) -> Result<Token, ParseError> {
             State::Char(_, _, _) => accumulator.push(state),
             State::Symbol(_, _, _) => accumulator.push(state),
             State::Num(_, _, _) => accumulator.push(state),
-            State::Parsed(token) => {
-                lst.push(token);
+            State::Parsed(token, line, column) => {
+                lst.push(quoted(&mut quotes, token));
+                spans.push(Span { line, column });
+            }
+            State::Quote(c, line, column) if accumulator.is_empty() => {
+                quotes.push(State::Quote(c, line, column))
+            }
+            State::Quote(c, line, column) => {
+                return Err(ParseError::InvalidCharacter(c, line, column))
             }
             _ => {}
         }
     }
 
-    Ok(Token::Lst(lst))
+    if let Some(State::Quote(c, line, column)) = quotes.first() {
+        return Err(ParseError::InvalidCharacter(*c, *line, *column));
+    }
+
+    Ok((Token::list(lst), spans))
 }
 
-pub struct Parser {}
+// where the forms of a source start: forms[i] is where the i-th top-level
+// form does, and lists where each list nested in them does, by the address
+// of its elements, which stays the same however often the list is cloned
+#[derive(Debug, Clone, Default)]
+pub struct Spans {
+    pub forms: Vec<Span>,
+    lists: HashMap<usize, Span>,
+}
+
+impl Spans {
+    pub fn of(&self, lst: &Arc<[Token]>) -> Option<Span> {
+        if self.lists.is_empty() {
+            return None;
+        }
+        self.lists.get(&(lst.as_ptr() as usize)).copied()
+    }
+
+    fn insert(&mut self, token: &Token, span: Span) {
+        match token {
+            Token::Lst(lst) if !lst.is_empty() => {
+                self.lists.insert(lst.as_ptr() as usize, span);
+            }
+            _ => {}
+        }
+    }
+}
+
+// the characters of states up to the next whitespace or list, as written
+fn stray_text(states: &[State]) -> String {
+    let mut text = String::new();
+    for state in states {
+        match state {
+            State::Char(c, _, _) | State::Num(c, _, _) | State::Symbol(c, _, _) => text.push(*c),
+            State::Quote('@', _, _) => text.push_str(",@"),
+            State::Quote(c, _, _) => text.push(*c),
+            _ => break,
+        }
+    }
+    text
+}
+
+pub struct Parser {
+    symbols: Interner,
+}
 
 impl Parser {
-    pub fn parse(&self, input: String) -> Result<Token, ParseError> {
+    pub fn new(symbols: Interner) -> Self {
+        Parser { symbols }
+    }
+
+    // the forms of spans are the elements of the outermost list, which are
+    // the top-level forms once the engine has wrapped its input in brackets
+    pub fn parse_with_spans(&self, input: String) -> Result<(Token, Spans), ParseError> {
         let mut line: i32 = 1;
         let mut collumn: i32 = 0;
 
         let mut states: Vec<State> = vec![];
+        let mut spans = Spans::default();
+        let mut in_string = false;
 
         for c in input.chars() {
             if is_newline(c) {
                 line += 1;
+                collumn = 0;
                 continue;
             }
 
@@ -281,26 +379,31 @@ impl Parser {
                 }
                 ')' => {
                     let mut substate: Vec<State> = vec![];
-                    loop {
+                    let (start_line, start_collumn) = loop {
                         match states.last() {
-                            Some(State::LstStart(_, _)) => {
-                                break;
+                            Some(State::LstStart(lin, col)) => {
+                                break (*lin, *col);
                             }
                             None => {
                                 return Err(ParseError::MissingBracket(line));
                             }
                             _ => {
-                                substate.insert(0, states.pop().unwrap());
+                                substate.push(states.pop().unwrap());
                             }
                         }
-                    }
+                    };
 
                     states.pop();
+                    substate.reverse();
 
-                    match parse_list(substate) {
-                        Ok(token) => states.push(State::Parsed(token)),
-                        Err(err) => return Err(err),
-                    };
+                    let (token, list_spans) = parse_list(&self.symbols, substate)?;
+                    if let Token::Lst(lst) = &token {
+                        for (item, span) in lst.iter().zip(&list_spans) {
+                            spans.insert(item, *span);
+                        }
+                    }
+                    states.push(State::Parsed(token, start_line, start_collumn));
+                    spans.forms = list_spans;
                 }
                 ' ' => match states.last() {
                     Some(State::Whitespace(_, _)) => {}
@@ -314,7 +417,18 @@ impl Parser {
                 '0'..='9' => {
                     states.push(State::Num(c, line, collumn));
                 }
+                '\'' | '`' | ',' if !in_string => {
+                    states.push(State::Quote(c, line, collumn));
+                }
+                '@' if matches!(states.last(), Some(State::Quote(',', _, _))) => {
+                    states.pop();
+                    states.push(State::Quote(c, line, collumn - 1));
+                }
                 _ => {
+                    // quotes inside "..." are part of the string
+                    if c == '"' {
+                        in_string = !in_string;
+                    }
                     states.push(State::Symbol(c, line, collumn));
                 }
             };
@@ -322,13 +436,64 @@ impl Parser {
 
         let mut result: Vec<Token> = vec![];
 
-        for state in states {
+        for (i, state) in states.iter().enumerate() {
             match state {
-                State::Parsed(token) => result.push(token),
-                _ => return Err(ParseError::UnknownError),
+                State::Parsed(token, _, _) => result.push(token.clone()),
+                State::LstStart(line, _) => return Err(ParseError::MissingBracket(*line)),
+                State::Whitespace(_, _) => {}
+                state => {
+                    let (line, collumn) = position(state);
+                    return Err(ParseError::InvalidSymbol(
+                        stray_text(&states[i..]),
+                        line,
+                        collumn,
+                    ));
+                }
             }
         }
 
-        Ok(result.first().unwrap().clone())
+        match result.first() {
+            Some(token) => Ok((token.clone(), spans)),
+            None => Err(ParseError::UnknownError),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Result<(Token, Spans), ParseError> {
+        Parser::new(Interner::new()).parse_with_spans(format!("({})", source))
+    }
+
+    #[test]
+    fn nested_lists_have_spans() {
+        let (forms, spans) = parse("(f 1)\n(g\n  (h 2))").unwrap();
+        assert_eq!(
+            spans.forms,
+            [Span { line: 1, column: 2 }, Span { line: 2, column: 1 }]
+        );
+
+        let forms = match forms {
+            Token::Lst(forms) => forms,
+            _ => unreachable!(),
+        };
+        let nested = match &forms[1] {
+            Token::Lst(g) => g[1].clone(),
+            _ => unreachable!(),
+        };
+        match nested {
+            Token::Lst(h) => assert_eq!(spans.of(&h), Some(Span { line: 3, column: 3 })),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn stray_text_is_shown_as_written() {
+        match parse("a) b@c (d") {
+            Err(ParseError::InvalidSymbol(text, 1, _)) => assert_eq!(text, "b@c"),
+            res => panic!("expected an invalid symbol, got {:?}", res),
+        }
     }
 }
@@ -1,35 +1,100 @@
-use crate::lib::token::Token;
+use crate::symbol::{sym, Interner};
+use crate::token::Token;
 
-pub fn print_token(token: Token) {
-    print_token_with_depth(token, 0);
+use std::sync::Arc;
+
+pub fn print_token(symbols: &Interner, token: Token) {
+    print_token_with_depth(symbols, token, 0);
     println!();
 }
 
-pub fn print_token_with_depth(token: Token, depth: i32) {
+pub fn print_token_with_depth(symbols: &Interner, token: Token, depth: i32) {
     match token {
-        Token::Lst(lst) => print_lst(lst, depth),
-        Token::Str(str) => print!("{} ", str),
-        Token::Var(var) => print!("{} ", var),
+        Token::Lst(_) if stream_prefix(&token).is_some() => {
+            let (items, more) = stream_prefix(&token).unwrap();
+            let mut items: Vec<Token> = items.into_iter().cloned().collect();
+            if more {
+                items.push(symbols.str("..."));
+            }
+            print_lst(symbols, items.into(), depth)
+        }
+        Token::Lst(lst) => print_lst(symbols, lst, depth),
+        Token::Str(str) => print!("{} ", symbols.resolve(str)),
+        Token::Var(var) => print!("{} ", symbols.resolve(var)),
         Token::Number(num) => print!("{} ", num),
         Token::Bool(b) => print!("{} ", b),
-        Token::Keyword(keyword) => print!("{} ", keyword),
-        Token::Wildcard(wildcard) => print!("{} ", wildcard),
-        Token::BinaryOp(op) => print!("{} ", op),
-        Token::UnaryOp(op) => print!("{} ", op),
+        Token::Keyword(keyword) => print!("{} ", symbols.resolve(keyword)),
+        Token::Wildcard(wildcard) => print!("{} ", symbols.resolve(wildcard)),
+        Token::BinaryOp(op) => print!("{} ", symbols.resolve(op)),
+        Token::UnaryOp(op) => print!("{} ", symbols.resolve(op)),
         _ => print!(" "),
     }
 }
 
-fn print_lst(token: Vec<Token>, depth: i32) {
+fn print_lst(symbols: &Interner, token: Arc<[Token]>, depth: i32) {
     if depth > 0 {
-        print!("\n");
+        println!();
     }
     for _ in 0..depth {
         print!("  ");
     }
     print!("( ");
-    for item in token {
-        print_token_with_depth(item, depth + 1);
+    for item in token.iter() {
+        print_token_with_depth(symbols, item.clone(), depth + 1);
     }
     print!(") ");
 }
+
+// single line rendering used by error messages, e.g. "(fib (- N 1))"
+pub fn render_token(symbols: &Interner, token: &Token) -> String {
+    match token {
+        Token::Lst(_) if stream_prefix(token).is_some() => {
+            let (items, more) = stream_prefix(token).unwrap();
+            let mut items: Vec<String> = items.iter().map(|t| render_token(symbols, t)).collect();
+            if more {
+                items.push("...".to_owned());
+            }
+            format!("({})", items.join(" "))
+        }
+        Token::Lst(lst) => {
+            let items: Vec<String> = lst.iter().map(|t| render_token(symbols, t)).collect();
+            format!("({})", items.join(" "))
+        }
+        Token::Str(str) => symbols.resolve(*str).to_string(),
+        Token::Var(var) => symbols.resolve(*var).to_string(),
+        Token::Number(num) => num.to_string(),
+        Token::Bool(b) => b.to_string(),
+        Token::Keyword(keyword) => symbols.resolve(*keyword).to_string(),
+        Token::Wildcard(wildcard) => symbols.resolve(*wildcard).to_string(),
+        Token::BinaryOp(op) => symbols.resolve(*op).to_string(),
+        Token::UnaryOp(op) => symbols.resolve(*op).to_string(),
+        Token::Unknown => "?".to_owned(),
+    }
+}
+
+// the elements of a stream forced so far, and whether there are more. a
+// stream is printed as a list, ending in ... while its tail is unforced
+fn stream_prefix(token: &Token) -> Option<(Vec<&Token>, bool)> {
+    let mut items = vec![];
+    let mut token = token;
+    loop {
+        let lst = match token {
+            Token::Lst(lst) => lst,
+            _ => return Some((items, true)),
+        };
+        match &**lst {
+            [Token::Keyword(sym::STREAM_CONS), head, tail] => {
+                items.push(match head {
+                    Token::Lst(quoted) if matches!(&**quoted, [Token::Keyword(sym::QUOTE), _]) => {
+                        &quoted[1]
+                    }
+                    head => head,
+                });
+                token = tail;
+            }
+            [] if !items.is_empty() => return Some((items, false)),
+            _ if items.is_empty() => return None,
+            _ => return Some((items, true)),
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use crate::token::Token;
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// The id of a name held by an [`Interner`], so comparing two names is
+/// comparing two integers. Ids are only meaningful to the interner that gave
+/// them out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+// names the evaluator itself looks for, interned first by every Interner so
+// their ids are known in advance
+pub mod sym {
+    use super::Symbol;
+
+    pub const TRY: Symbol = Symbol(0);
+    pub const CATCH: Symbol = Symbol(1);
+    pub const ERROR: Symbol = Symbol(2);
+    pub const TRANSACTION: Symbol = Symbol(3);
+    pub const IMPORT: Symbol = Symbol(4);
+    pub const ONLY: Symbol = Symbol(5);
+    pub const QUOTE: Symbol = Symbol(6);
+    pub const QUASIQUOTE: Symbol = Symbol(7);
+    pub const UNQUOTE: Symbol = Symbol(8);
+    pub const SPLICE: Symbol = Symbol(9);
+    // a keyword no source can write, see evaluator::lazy
+    pub const THUNK: Symbol = Symbol(10);
+    pub const STREAM_CONS: Symbol = Symbol(11);
+    pub const MATCH_CASE: Symbol = Symbol(12);
+    pub const SOLUTIONS: Symbol = Symbol(13);
+    // a keyword no source can write either, see evaluator::solve
+    pub const SEARCH: Symbol = Symbol(14);
+
+    pub(super) const PREDEFINED: [&str; 15] = [
+        ".try",
+        ".catch",
+        ".error",
+        ".transaction",
+        ".import",
+        ":only",
+        ".quote",
+        ".quasiquote",
+        ".unquote",
+        ".unquote-splicing",
+        "#thunk",
+        ".stream-cons",
+        ".match-case",
+        ".solutions",
+        "#search",
+    ];
+}
+
+#[derive(Default)]
+struct SymbolTable {
+    names: Vec<Arc<str>>,
+    ids: HashMap<Arc<str>, Symbol>,
+    // the symbols given out by hidden, by name
+    hidden: HashMap<Arc<str>, Symbol>,
+}
+
+/// Maps names to symbols and back. Clones share the same table.
+#[derive(Clone)]
+pub struct Interner(Arc<RwLock<SymbolTable>>);
+
+impl Default for Interner {
+    fn default() -> Self {
+        Interner::new()
+    }
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        let interner = Interner(Arc::new(RwLock::new(SymbolTable::default())));
+        for name in sym::PREDEFINED {
+            interner.intern(name);
+        }
+        interner
+    }
+
+    /// The symbol of `name`, given a new id the first time it is seen.
+    pub fn intern(&self, name: &str) -> Symbol {
+        if let Some(symbol) = self.read().ids.get(name) {
+            return *symbol;
+        }
+
+        let mut table = self.0.write().unwrap_or_else(|err| err.into_inner());
+        if let Some(symbol) = table.ids.get(name) {
+            return *symbol;
+        }
+
+        let symbol = Symbol(table.names.len() as u32);
+        let name: Arc<str> = Arc::from(name);
+        table.names.push(name.clone());
+        table.ids.insert(name, symbol);
+        symbol
+    }
+
+    /// A new symbol named `name` that [`intern`](Interner::intern) never gives
+    /// out, so no source can refer to it.
+    pub fn fresh(&self, name: &str) -> Symbol {
+        let mut table = self.0.write().unwrap_or_else(|err| err.into_inner());
+        let symbol = Symbol(table.names.len() as u32);
+        table.names.push(Arc::from(name));
+        symbol
+    }
+
+    /// The symbol standing for `name` where no source can refer to it, as
+    /// the private definitions of a file are named. Unlike
+    /// [`fresh`](Interner::fresh) it is the same for every call, so clauses
+    /// read back from an image or a log still refer to each other.
+    pub fn hidden(&self, name: &str) -> Symbol {
+        let mut table = self.0.write().unwrap_or_else(|err| err.into_inner());
+        if let Some(symbol) = table.hidden.get(name) {
+            return *symbol;
+        }
+
+        let symbol = Symbol(table.names.len() as u32);
+        let name: Arc<str> = Arc::from(name);
+        table.names.push(name.clone());
+        table.hidden.insert(name, symbol);
+        symbol
+    }
+
+    /// Whether `symbol` is the one [`intern`](Interner::intern) gives for
+    /// its name.
+    pub fn is_interned(&self, symbol: Symbol) -> bool {
+        let table = self.read();
+        match table.names.get(symbol.0 as usize) {
+            Some(name) => table.ids.get(name) == Some(&symbol),
+            None => false,
+        }
+    }
+
+    /// The name of `symbol`, or `#` and its id if another interner gave it.
+    pub fn resolve(&self, symbol: Symbol) -> Arc<str> {
+        match self.read().names.get(symbol.0 as usize) {
+            Some(name) => name.clone(),
+            None => Arc::from(format!("#{}", symbol.0)),
+        }
+    }
+
+    pub fn var(&self, name: &str) -> Token {
+        Token::Var(self.intern(name))
+    }
+
+    pub fn str(&self, name: &str) -> Token {
+        Token::Str(self.intern(name))
+    }
+
+    pub fn keyword(&self, name: &str) -> Token {
+        Token::Keyword(self.intern(name))
+    }
+
+    pub fn binary_op(&self, name: &str) -> Token {
+        Token::BinaryOp(self.intern(name))
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, SymbolTable> {
+        self.0.read().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_round_trip() {
+        let symbols = Interner::new();
+        let a = symbols.intern("a");
+        assert_eq!(symbols.intern("a"), a);
+        assert_ne!(symbols.intern("b"), a);
+        assert_eq!(&*symbols.resolve(a), "a");
+
+        // clones share the table
+        assert_eq!(symbols.clone().intern("a"), a);
+        assert_eq!(&*symbols.resolve(Symbol(1000)), "#1000");
+    }
+
+    #[test]
+    fn predefined_names_have_known_ids() {
+        let symbols = Interner::new();
+        assert_eq!(symbols.intern(".try"), sym::TRY);
+        assert_eq!(symbols.intern("#thunk"), sym::THUNK);
+        assert_eq!(symbols.intern(".match-case"), sym::MATCH_CASE);
+        assert_eq!(symbols.intern(".solutions"), sym::SOLUTIONS);
+    }
+
+    #[test]
+    fn fresh_symbols_are_never_interned() {
+        let symbols = Interner::new();
+        let x = symbols.intern("X");
+        let fresh = symbols.fresh("X");
+        assert_ne!(fresh, x);
+        assert_ne!(symbols.fresh("X"), fresh);
+        assert_eq!(symbols.intern("X"), x);
+        assert_eq!(&*symbols.resolve(fresh), "X");
+        assert!(symbols.is_interned(x) && !symbols.is_interned(fresh));
+    }
+
+    #[test]
+    fn hidden_symbols_are_the_same_for_a_name() {
+        let symbols = Interner::new();
+        let hidden = symbols.hidden("m/helper");
+        assert_eq!(symbols.hidden("m/helper"), hidden);
+        assert_ne!(symbols.intern("m/helper"), hidden);
+        assert!(!symbols.is_interned(hidden));
+    }
+}
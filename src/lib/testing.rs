@@ -0,0 +1,58 @@
+// helpers shared by the tests of every module
+
+use crate::engine::Engine;
+use crate::error::{EvalError, LexError};
+
+use std::path::{Path, PathBuf};
+
+// the value of input rendered, or the error it failed with
+pub fn eval(engine: &mut Engine, input: &str) -> String {
+    match engine.eval_str(input) {
+        Ok(token) => engine.render(&token),
+        Err(err) => err.to_string(),
+    }
+}
+
+// the error program fails to evaluate with
+pub fn eval_err(engine: &mut Engine, program: &str) -> EvalError {
+    match engine.eval_str(program) {
+        Err(LexError::EvalError(err, _)) => err,
+        res => panic!("expected an error from {}, got {:?}", program, res),
+    }
+}
+
+// a directory of a test's own under the temporary one, emptied when made and
+// removed once dropped, even by a test that fails
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("lex-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.0.join(path)
+    }
+
+    // writes contents to path in the directory, making the directories it is
+    // in first
+    pub fn write(&self, path: &str, contents: &str) -> PathBuf {
+        let path = self.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -1,13 +1,112 @@
+pub use crate::symbol::Symbol;
+
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::sync::Arc;
+
+/// A parsed form or a value. Lists are shared, so cloning a token never
+/// copies a tree.
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum Token {
-    Lst(Vec<Token>),  // list
-    Var(String),      // variable
-    Str(String),      // string
-    Keyword(String),  // string
-    Number(f32),      // integer
-    Bool(bool),       // booelan
-    BinaryOp(String), // symbol like operator
-    UnaryOp(String),  // symbol like operator
-    Wildcard(String),
+    Lst(Arc<[Token]>), // list
+    Var(Symbol),       // variable
+    Str(Symbol),       // string
+    Keyword(Symbol),   // string
+    Number(f32),       // integer
+    Bool(bool),        // booelan
+    BinaryOp(Symbol),  // symbol like operator
+    UnaryOp(Symbol),   // symbol like operator
+    Wildcard(Symbol),
     Unknown,
 }
+
+// numbers compare by their bits, with every 0 and every NaN alike, so that
+// tokens can be keys even when they hold NaN
+fn number_bits(num: f32) -> u32 {
+    if num == 0.0 {
+        0
+    } else if num.is_nan() {
+        f32::NAN.to_bits()
+    } else {
+        num.to_bits()
+    }
+}
+
+impl PartialEq for Token {
+    fn eq(&self, other: &Token) -> bool {
+        match (self, other) {
+            (Token::Lst(a), Token::Lst(b)) => a == b,
+            (Token::Number(a), Token::Number(b)) => number_bits(*a) == number_bits(*b),
+            (Token::Bool(a), Token::Bool(b)) => a == b,
+            (Token::Var(a), Token::Var(b))
+            | (Token::Str(a), Token::Str(b))
+            | (Token::Keyword(a), Token::Keyword(b))
+            | (Token::BinaryOp(a), Token::BinaryOp(b))
+            | (Token::UnaryOp(a), Token::UnaryOp(b))
+            | (Token::Wildcard(a), Token::Wildcard(b)) => a == b,
+            (Token::Unknown, Token::Unknown) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Token {}
+
+impl Hash for Token {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self).hash(state);
+        match self {
+            Token::Lst(lst) => lst.hash(state),
+            Token::Number(num) => number_bits(*num).hash(state),
+            Token::Bool(b) => b.hash(state),
+            Token::Var(s)
+            | Token::Str(s)
+            | Token::Keyword(s)
+            | Token::BinaryOp(s)
+            | Token::UnaryOp(s)
+            | Token::Wildcard(s) => s.hash(state),
+            Token::Unknown => {}
+        }
+    }
+}
+
+impl Token {
+    pub fn list(items: Vec<Token>) -> Self {
+        Token::Lst(items.into())
+    }
+
+    // a ground token holds no variable or wildcard
+    pub fn is_ground(&self) -> bool {
+        match self {
+            Token::Lst(lst) => lst.iter().all(Token::is_ground),
+            Token::Var(_) | Token::Wildcard(_) => false,
+            _ => true,
+        }
+    }
+}
+
+/// The position of a token in its source, counted from 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub line: i32,
+    pub column: i32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Token;
+    use std::collections::HashMap;
+
+    #[test]
+    fn numbers_are_keys_even_when_nan() {
+        let mut table = HashMap::new();
+        table.insert(Token::list(vec![Token::Number(f32::NAN)]), 1);
+        table.insert(Token::Number(0.0), 2);
+
+        let nan = Token::list(vec![Token::Number(-f32::NAN)]);
+        assert_eq!(table.get(&nan), Some(&1));
+        assert_eq!(table.get(&Token::Number(-0.0)), Some(&2));
+        assert_ne!(Token::Number(1.0), Token::Number(f32::NAN));
+    }
+}
@@ -1,24 +1,22 @@
 extern crate clap;
 extern crate rustyline;
 
-mod lib;
-use lib::engine::Engine;
+use lex_lang::{Engine, EngineOptions, LexError};
 use std::path::PathBuf;
+use std::time::Duration;
 
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 
-use clap::{arg, Command};
+use clap::{arg, Command, ErrorKind};
 
 struct Repl {
     engine: Engine,
 }
 
 impl Repl {
-    pub fn new() -> Self {
-        Repl {
-            engine: Engine::new(),
-        }
+    pub fn new(engine: Engine) -> Self {
+        Repl { engine }
     }
 
     pub fn run(&mut self) {
@@ -33,9 +31,8 @@ impl Repl {
             match readline {
                 Ok(line) => {
                     rl.add_history_entry(line.as_str());
-                    let mut l = line.clone();
-                    match self.engine.parse(l) {
-                        Ok(token) => println!("{:?}", token),
+                    match self.engine.eval_str(&line) {
+                        Ok(token) => println!("{}", self.engine.render(&token)),
                         Err(e) => println!("{}", e),
                     }
                 }
@@ -54,31 +51,166 @@ impl Repl {
             }
         }
 
-        rl.save_history("history.txt").unwrap();
+        if let Err(err) = rl.save_history("history.txt") {
+            println!("Error: {:?}", err);
+        }
     }
 }
 
+fn run_file(engine: &mut Engine, path: &str) -> Result<(), LexError> {
+    engine.eval_file(path)?;
+    Ok(())
+}
+
+// evaluates source after the image given, if any, and writes the clauses
+// both define to an image
+fn build(
+    source: &str,
+    output: &str,
+    options: EngineOptions,
+    image: Option<&str>,
+) -> Result<(), LexError> {
+    let mut engine = engine(options, image)?;
+    run_file(&mut engine, source)?;
+    engine.save_image(output)
+}
+
+fn engine(options: EngineOptions, image: Option<&str>) -> Result<Engine, LexError> {
+    let mut engine = Engine::with_options(options)?;
+    if let Some(image) = image {
+        engine.load_image(image)?;
+    }
+    Ok(engine)
+}
+
+// the interpreter recurses on the native stack for calls that aren't tail
+// calls, so give it room for a --max-depth well above the default
+const STACK_SIZE: usize = 256 * 1024 * 1024;
+
 fn main() {
-    let matches = Command::new("lex")
-        .arg(arg!(<PATH>..."file path"))
-        .get_matches();
-
-    match matches.subcommand() {
-        _ => {
-            let p = matches.value_of("PATH");
-
-            match p {
-                Some(path) => {
-                    let file = std::fs::read_to_string(path).unwrap();
-                    match Engine::new().parse(file.to_owned()) {
-                        Err(err) => println!("{:?}", err),
-                        _ => (),
-                    }
+    let mut cli = Command::new("lex")
+        .subcommand(
+            Command::new("build")
+                .about("writes the clauses defined by a file to an image")
+                .arg(arg!(<SOURCE> "file path"))
+                .arg(arg!(-o --output <IMAGE> "image path")),
+        )
+        .subcommand(
+            Command::new("run")
+                .about("runs the package described by a Lex.toml")
+                .arg(arg!([DIR] "package directory, the current one if none")),
+        )
+        .arg(arg!([PATH] "file path, or none for a repl"))
+        .arg(
+            arg!(--image <IMAGE> "image to load first")
+                .required(false)
+                .global(true),
+        )
+        .arg(
+            arg!(--database <LOG> "file to keep the clauses defined in across runs")
+                .required(false)
+                .global(true),
+        )
+        .arg(
+            arg!(--"max-depth" <DEPTH> "how deeply non-tail calls may nest")
+                .required(false)
+                .global(true)
+                .validator(|depth| depth.parse::<usize>()),
+        )
+        .arg(
+            arg!(--"max-steps" <STEPS> "how many definitions may run before giving up")
+                .required(false)
+                .global(true)
+                .validator(|steps| steps.parse::<u64>()),
+        )
+        .arg(
+            arg!(--timeout <MILLISECONDS> "how long an evaluation may run")
+                .required(false)
+                .global(true)
+                .validator(|timeout| timeout.parse::<u64>()),
+        )
+        .arg(
+            arg!(--compile "run definitions on the bytecode vm")
+                .required(false)
+                .global(true),
+        )
+        .arg(
+            arg!(--lazy "evaluate arguments only once a clause needs them")
+                .required(false)
+                .global(true),
+        )
+        .arg(
+            arg!(-I --include <DIR> "directory to look for imported modules in")
+                .required(false)
+                .global(true)
+                .multiple_occurrences(true),
+        );
+    let matches = cli.clone().get_matches();
+    if matches.subcommand().is_some() && matches.is_present("PATH") {
+        cli.error(
+            ErrorKind::ArgumentConflict,
+            "a file path can't be given along with a subcommand",
+        )
+        .exit();
+    }
+
+    // the engine's flags may be given before a subcommand or after it, and
+    // are found with its arguments either way
+    let flags = matches.subcommand().map_or(&matches, |(_, sub)| sub);
+    let mut options = EngineOptions::default();
+    if let Some(depth) = flags.value_of("max-depth") {
+        options.max_depth = depth.parse().unwrap_or(options.max_depth);
+    }
+    if let Some(steps) = flags.value_of("max-steps") {
+        options.max_steps = steps.parse().ok();
+    }
+    if let Some(timeout) = flags.value_of("timeout") {
+        options.timeout = timeout.parse().ok().map(Duration::from_millis);
+    }
+
+    options.compile = flags.is_present("compile");
+    options.lazy = flags.is_present("lazy");
+    options.database = flags.value_of("database").map(PathBuf::from);
+    if let Some(dirs) = flags.values_of("include") {
+        options.search_path = dirs.map(PathBuf::from).collect();
+    }
+
+    let build_args = matches.subcommand_matches("build").map(|build| {
+        (
+            build.value_of("SOURCE").unwrap_or_default().to_owned(),
+            build.value_of("output").unwrap_or_default().to_owned(),
+        )
+    });
+    let package = matches
+        .subcommand_matches("run")
+        .map(|run| run.value_of("DIR").unwrap_or(".").to_owned());
+    let path = matches.value_of("PATH").map(|path| path.to_owned());
+    let image = flags.value_of("image").map(|image| image.to_owned());
+
+    let interpreter = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || {
+            let res = match (build_args, package, path) {
+                (Some((source, output)), _, _) => {
+                    build(&source, &output, options, image.as_deref())
                 }
-                None => {
-                    Repl::new().run();
+                (None, Some(package), _) => engine(options, image.as_deref())
+                    .and_then(|mut engine| engine.eval_package(package).map(|_| ())),
+                (None, None, Some(path)) => engine(options, image.as_deref())
+                    .and_then(|mut engine| run_file(&mut engine, &path)),
+                (None, None, None) => {
+                    engine(options, image.as_deref()).map(|engine| Repl::new(engine).run())
                 }
+            };
+            if let Err(err) = res {
+                println!("{}", err);
             }
+        });
+
+    match interpreter {
+        Ok(handle) => {
+            let _ = handle.join();
         }
-    };
+        Err(err) => println!("Error: {:?}", err),
+    }
 }